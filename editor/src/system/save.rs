@@ -2,17 +2,18 @@ use amethyst::{
     assets::AssetStorage,
     core::math::Point3,
     derive::SystemDesc,
-    ecs::{Join, Read, ReadStorage, System, SystemData, WriteStorage},
+    ecs::{Join, Read, ReadStorage, System, SystemData, Write, WriteStorage},
     input::{InputHandler, StringBindings},
     tiles::{Map, MapStorage, TileMap},
     utils::application_root_dir,
     winit,
 };
-use sanity_lib::{assets::Pairs, tile::RoomTile};
+use sanity_lib::{assets::Pairs, mapgen::DEFAULT_WEIGHT, tile::RoomTile};
 
 #[derive(SystemDesc, Default)]
 pub struct SaveSystem {
     saving: bool,
+    toggling: bool,
     pairs: Option<Pairs>,
 }
 
@@ -22,15 +23,30 @@ impl<'s> System<'s> for SaveSystem {
         WriteStorage<'s, TileMap<RoomTile>>,
         ReadStorage<'s, sanity_lib::assets::PairsHandle>,
         Read<'s, InputHandler<StringBindings>>,
-        Read<'s, AssetStorage<sanity_lib::assets::Pairs>>,
+        Write<'s, AssetStorage<sanity_lib::assets::Pairs>>,
         ReadStorage<'s, crate::state::SavePath>,
+        ReadStorage<'s, crate::state::edit::Selected>,
     );
 
     fn run(
         &mut self,
-        (mut tilemaps, pairs_handles, input, pairs_storage, save_paths): Self::SystemData,
+        (
+            mut tilemaps,
+            pairs_handles,
+            input,
+            mut pairs_storage,
+            save_paths,
+            selections,
+        ): Self::SystemData,
     ) {
-        for (tilemap, pairs, save_path) in (&mut tilemaps, &pairs_handles, &save_paths).join() {
+        for (tilemap, pairs, save_path, selected) in (
+            &mut tilemaps,
+            &pairs_handles,
+            &save_paths,
+            selections.maybe(),
+        )
+            .join()
+        {
             let dim = *tilemap.dimensions();
 
             if self.pairs.is_none() && pairs_storage.get(pairs).is_some() {
@@ -81,12 +97,57 @@ impl<'s> System<'s> for SaveSystem {
                     }
                 }
 
-                p.walkable = self.pairs.clone().unwrap().walkable;
-                p.null = self.pairs.clone().unwrap().null;
+                // tile definitions are edited in place on the loaded asset so the ui can show them
+                let walkable = input.action_is_down("walkable").unwrap_or(false);
+                let opaque = input.action_is_down("opaque").unwrap_or(false);
+                let weight_up = input.action_is_down("weight_up").unwrap_or(false);
+                let weight_down = input.action_is_down("weight_down").unwrap_or(false);
+                let weight_reset = input.action_is_down("weight_reset").unwrap_or(false);
+                let weight = weight_up || weight_down || weight_reset;
+                if let (Some(sprite), Some(asset)) = (
+                    selected
+                        .and_then(|s| s.0)
+                        .and_then(|pos| tilemap.get(&pos))
+                        .and_then(|t| t.sprite),
+                    pairs_storage.get_mut(pairs),
+                ) {
+                    if (walkable || opaque || weight) && !self.toggling {
+                        let def = asset.tiles.entry(sprite).or_default();
+                        if walkable {
+                            def.walkable = !def.walkable;
+                        }
+                        if opaque {
+                            def.opaque = !def.opaque;
+                        }
+                        // weights double or halve, starting from the default of the generator
+                        let current = def.weight.unwrap_or(DEFAULT_WEIGHT);
+                        if weight_up {
+                            def.weight = Some((current * 2).max(1));
+                        }
+                        if weight_down {
+                            def.weight = Some((current / 2).max(1));
+                        }
+                        if weight_reset {
+                            def.weight = None;
+                        }
+                    }
+                }
+                self.toggling = walkable || opaque || weight;
+
+                if let Some(asset) = pairs_storage.get(pairs) {
+                    p.tiles = asset.tiles.clone();
+                    p.roles = asset.roles.clone();
+                    p.symmetry = asset.symmetry.clone();
+                    p.max_tiles = asset.max_tiles;
+                }
 
                 if input.key_is_down(winit::VirtualKeyCode::S) && !self.saving {
-                    let s =
-                        ron::ser::to_string_pretty(&p, ron::ser::PrettyConfig::default()).unwrap();
+                    // rules implied by the symmetry are added back when the file is loaded
+                    let s = ron::ser::to_string_pretty(
+                        &p.compacted(),
+                        ron::ser::PrettyConfig::default(),
+                    )
+                    .unwrap();
                     let save = application_root_dir()
                         .unwrap()
                         .parent()
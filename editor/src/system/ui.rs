@@ -1,7 +1,8 @@
 use amethyst::{
+    assets::AssetStorage,
     derive::SystemDesc,
     ecs::{
-        prelude::{Entity, System, SystemData, WriteStorage},
+        prelude::{Entity, Read, System, SystemData, WriteStorage},
         Join, ReadStorage,
     },
     tiles::{Map, MapStorage, TileMap},
@@ -20,22 +21,35 @@ impl<'a> System<'a> for UISystem {
         UiFinder<'a>,
         ReadStorage<'a, TileMap<RoomTile>>,
         ReadStorage<'a, crate::state::edit::Selected>,
+        ReadStorage<'a, sanity_lib::assets::PairsHandle>,
+        Read<'a, AssetStorage<sanity_lib::assets::Pairs>>,
     );
 
     fn run(&mut self, data: Self::SystemData) {
-        let (mut ui_text, finder, tilemaps, selected) = data;
+        let (mut ui_text, finder, tilemaps, selected, pairs_handles, pairs_storage) = data;
 
         if self.south_list.is_none() {
             self.south_list = finder.find("south_list");
         }
 
-        for (t, s) in (&tilemaps, &selected).join() {
+        for (t, s, pairs) in (&tilemaps, &selected, &pairs_handles).join() {
             if let Some(e) = self.south_list {
                 if let Some(ui) = ui_text.get_mut(e) {
                     if let Some(p) = s.0 {
+                        let idx = (p.x + p.y * t.dimensions().x) as usize;
+                        let def = pairs_storage
+                            .get(pairs)
+                            .map(|pairs| pairs.tile(idx))
+                            .unwrap_or_default();
+
                         ui.text = format!(
-                            "{:?} \n south:{:?} \n east:{:?}",
-                            p.x + p.y * t.dimensions().x,
+                            "{:?} {} \n walkable (W):{} opaque (O):{} \n tags:{:?} weight (+/-/Backspace):{} \n south:{:?} \n east:{:?}",
+                            idx,
+                            def.name.unwrap_or_default(),
+                            def.walkable,
+                            def.opaque,
+                            def.tags,
+                            def.weight.map_or("default".to_string(), |w| w.to_string()),
                             t.get(&p)
                                 .map(|t| t.candidates.s.clone())
                                 .unwrap_or_default(),
@@ -21,6 +21,7 @@ use std::{iter::Cycle, vec::IntoIter};
 
 pub struct Music {
     pub music: Cycle<IntoIter<SourceHandle>>,
+    pub track: Option<String>, // set once a level asks for its own track
 }
 
 /// Loads an ogg audio track.
@@ -50,7 +51,7 @@ pub fn initialise_audio(world: &mut World) {
                 intro: load_audio_track(&loader, &world, INTRO_SOUND),
                 vo: load_audio_track(&loader, &world, VO_SOUND),
             },
-            Music { music },
+            Music { music, track: None },
         )
     };
 
@@ -60,6 +61,23 @@ pub fn initialise_audio(world: &mut World) {
     world.insert(music);
 }
 
+/// Loops a single track as the background music, unless it is already playing.
+pub fn play_music(world: &mut World, file: &str) {
+    if world.read_resource::<Music>().track.as_deref() == Some(file) {
+        return;
+    }
+
+    let track = {
+        let loader = world.read_resource::<Loader>();
+        load_audio_track(&loader, &world, file)
+    };
+
+    let mut music = world.write_resource::<Music>();
+    music.music = vec![track].into_iter().cycle();
+    music.track = Some(file.to_string());
+    world.write_resource::<AudioSink>().stop(); // the dj picks the new track straight away
+}
+
 pub fn play_intro(sounds: &Sounds, storage: &AssetStorage<Source>, output: Option<&Output>) {
     if let Some(ref output) = output.as_ref() {
         if let Some(sound) = storage.get(&sounds.intro) {
@@ -6,3 +6,11 @@ pub struct Exit;
 impl Component for Exit {
     type Storage = NullStorage<Self>;
 }
+
+/// The way back up, at the start of every level below the first.
+#[derive(Default)]
+pub struct StairsUp;
+
+impl Component for StairsUp {
+    type Storage = NullStorage<Self>;
+}
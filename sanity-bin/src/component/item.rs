@@ -1,9 +1,10 @@
 use amethyst::ecs::{Component, HashMapStorage};
 use rand::prelude::*;
+use serde::{Deserialize, Serialize};
 use std::convert::AsRef;
 use strum_macros::AsRefStr;
 
-#[derive(Debug, PartialEq, Clone, Copy, AsRefStr)]
+#[derive(Debug, PartialEq, Clone, Copy, AsRefStr, Serialize, Deserialize)]
 #[strum(serialize_all = "snake_case")]
 pub enum ItemType {
     Battery,
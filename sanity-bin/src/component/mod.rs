@@ -1,5 +1,4 @@
 mod collision;
-mod dijkstra;
 mod enemy;
 mod exit;
 mod health;
@@ -8,16 +7,17 @@ mod movement;
 mod player;
 mod position;
 mod projectile;
+mod regions;
 mod weapon;
 
 pub use collision::Collision;
-pub use dijkstra::Dijkstra;
 pub use enemy::Enemy;
-pub use exit::Exit;
+pub use exit::{Exit, StairsUp};
 pub use health::Health;
 pub use item::Item;
 pub use movement::MovementIntent;
 pub use player::Player;
 pub use position::Position;
 pub use projectile::Projectile;
+pub use regions::Regions;
 pub use weapon::Weapon;
@@ -1,7 +1,7 @@
 use amethyst::ecs::{Component, DenseVecStorage};
 
 pub struct MovementIntent {
-    pub dir: direction::CardinalDirection,
+    pub dir: direction::Direction,
     pub step: usize,
 }
 
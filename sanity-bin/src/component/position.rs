@@ -10,6 +10,7 @@ use std::ops::Add;
 pub struct Position {
     pub pos: Point,
     pub map: Entity,
+    pub z: u32, // storey, 0 is the bottom one
 }
 
 impl Component for Position {
@@ -21,21 +22,22 @@ impl Position {
     }
 
     pub fn xyz(&self) -> Point3<u32> {
-        Point3::new(self.pos.x as u32, self.pos.y as u32, 0)
+        Point3::new(self.pos.x as u32, self.pos.y as u32, self.z)
     }
 
     pub fn coord(&self) -> Coord {
         direction::Coord::new(self.pos.x as i32, self.pos.y as i32)
     }
 }
-impl Add<direction::CardinalDirection> for Position {
+impl Add<direction::Direction> for Position {
     type Output = Self;
 
-    fn add(self, other: direction::CardinalDirection) -> Self {
+    fn add(self, other: direction::Direction) -> Self {
         let c = other.coord();
         Self {
             pos: Point::new(self.pos.x + c.x, self.pos.y + c.y),
             map: self.map,
+            z: self.z,
         }
     }
 }
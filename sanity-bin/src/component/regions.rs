@@ -0,0 +1,13 @@
+use amethyst::ecs::{Component, HashMapStorage};
+use std::collections::BTreeSet;
+
+/// Rooms and corridors of the level on a walls map.
+pub struct Regions {
+    pub regions: sanity_lib::regions::Regions,
+    pub guarded: BTreeSet<usize>, // corridors next to the start, enemies don't spawn in them
+    pub storey: u32,              // the one they were found on, the others spawn anywhere
+}
+
+impl Component for Regions {
+    type Storage = HashMapStorage<Self>;
+}
@@ -12,8 +12,7 @@ impl Component for Weapon {
 }
 
 impl Weapon {
-    pub fn fire(&self) -> super::projectile::Projectile {
-        let mut rng = thread_rng();
+    pub fn fire<R: Rng>(&self, rng: &mut R) -> super::projectile::Projectile {
         super::projectile::Projectile::new(rng.gen_range(self.damage_range.0, self.damage_range.1))
     }
 }
@@ -37,7 +37,6 @@ use std::{fmt::Debug, marker::PhantomData};
 mod audio;
 mod component;
 mod gamedata;
-mod map;
 mod resource;
 mod state;
 mod system;
@@ -119,7 +118,11 @@ fn main() -> Result<()> {
             "player_system",
             &[],
         )
-        .with_base(system::ai::AISystem::default(), "ai_system", &[])
+        .with_running(
+            system::secret::SecretSystem::default(),
+            "secret_system",
+            &[],
+        )
         .with_base(
             system::movement::MovementSystem::default(),
             "movement_system",
@@ -131,6 +134,21 @@ fn main() -> Result<()> {
             &["movement_system"],
         )
         .with_running(system::spawn::SpawnSystem::default(), "spawn_system", &[])
+        .with_base(
+            system::dijkstra::DijkstraSystem::default(),
+            "dijkstra_system",
+            &["movement_system", "vis_system"],
+        )
+        .with_base(
+            system::ai::AISystem::default(),
+            "ai_system",
+            &["dijkstra_system"],
+        )
+        .with_base(
+            system::chunk::ChunkSystem::default(),
+            "chunk_system",
+            &["movement_system"],
+        )
         .with_base(
             system::hud::HUDSystem::default(),
             "hud_system",
@@ -152,6 +170,9 @@ fn main() -> Result<()> {
             &["collision_system"],
         )
         .with_base(Processor::<sanity_lib::assets::Pairs>::new(), "", &[])
+        .with_base(Processor::<resource::Levels>::new(), "", &[])
+        .with_base(Processor::<sanity_lib::assets::Vault>::new(), "", &[])
+        .with_base(Processor::<sanity_lib::assets::Example>::new(), "", &[])
         .with_base_bundle(AudioBundle::default())
         .with_base_bundle(
             RenderingBundle::<DefaultBackend>::new()
@@ -169,7 +190,13 @@ fn main() -> Result<()> {
     //let first_state = state::LoadingState::default();
     let first_state = state::IntroState::default();
 
+    let seed = resource::Seed::from_args();
+    log::info!("Run seed {}", seed.0);
+    let settings = resource::Settings::from_args();
+
     let mut game = Application::build(app_root.parent().unwrap().join("assets"), first_state)?
+        .with_resource(seed)
+        .with_resource(settings)
         .with_frame_limit(FrameRateLimitStrategy::Yield, 101)
         .build(game_data)?;
 
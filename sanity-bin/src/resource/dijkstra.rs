@@ -0,0 +1,49 @@
+use amethyst::ecs::Entity;
+use bracket_pathfinding::prelude::{DijkstraMap, Point};
+use std::collections::HashMap;
+
+/// What a shared Dijkstra map measures the distance to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Purpose {
+    Player,   // the player
+    Flee,     // lowest away from the player, for running from them
+    Exit,     // the exit down, when it is on the tilemap
+    Frontier, // the explored tiles next to unexplored ones
+}
+
+/// What the maps were last built from, they are only built again when it changes.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Built {
+    pub map: Entity,
+    pub player: (Point, u32),
+    pub version: u64,
+}
+
+/// Dijkstra maps of the current level shared by every system, see `DijkstraSystem`.
+/// Indices are those of `SanityMap`, with the storeys stacked.
+#[derive(Default)]
+pub struct DijkstraMaps {
+    pub maps: HashMap<Purpose, DijkstraMap>,
+    pub built: Option<Built>,
+    version: u64,
+}
+
+impl DijkstraMaps {
+    /// The map for `purpose` of the level on `map`, `None` until it has been built for it.
+    pub fn get(&self, map: Entity, purpose: Purpose) -> Option<&DijkstraMap> {
+        match self.built {
+            Some(built) if built.map == map => self.maps.get(&purpose),
+            _ => None,
+        }
+    }
+
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    /// Marks the maps out of date after the walls of the level changed, they are built again on
+    /// the next frame.
+    pub fn invalidate(&mut self) {
+        self.version += 1;
+    }
+}
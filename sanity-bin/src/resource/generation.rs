@@ -0,0 +1,443 @@
+use amethyst::{
+    core::math::{Point3, Vector3},
+    tiles::{MapStorage, TileMap},
+};
+use direction::Coord;
+use rand::rngs::StdRng;
+use sanity_lib::mapgen::{Budget, GenError, GenStats, Generator, Model, Progress};
+use sanity_lib::{
+    assets::{SpawnKind, Vault},
+    chunks::{ChunkCoord, Chunks},
+    regions::Regions,
+    tile::{FloorTile, RoomTile},
+};
+use std::{
+    collections::HashMap,
+    panic::{self, AssertUnwindSafe},
+    sync::{Arc, Mutex},
+    thread,
+};
+
+/// Everything a worker thread needs to build a level on its own.
+#[derive(Clone)]
+pub struct MapRequest {
+    pub level: usize,
+    pub width: u32,
+    pub height: u32,
+    pub start: Coord,
+    pub min_exit_distance: i32,
+    pub budget: Budget,
+    pub pairs: sanity_lib::assets::Pairs,
+    pub model: Model,
+    pub generator: Generator,
+    pub floor_pairs: Option<sanity_lib::assets::Pairs>,
+    pub vaults: Vec<Vault>,
+    pub seed: super::Seed,
+    pub storeys: u32,
+    pub storey: u32, // the one being generated when there are more than one
+    pub secret_doors: usize,
+}
+
+/// A finished level, ready to be copied on to the tilemaps of the room.
+pub struct GeneratedLevel {
+    pub level: usize,
+    pub walls: TileMap<RoomTile>,
+    pub floor: TileMap<FloorTile>,
+    pub exit: Coord,
+    pub spawns: Vec<(Coord, SpawnKind)>, // from the vaults, the exit is already in `exit`
+    pub regions: Regions,
+    pub surface: Option<Chunks>, // every chunk generated so far on a surface level
+    pub outcome: Result<GenStats, GenError>,
+}
+
+/// What a worker thread panicked with, for the log.
+fn panic_cause(cause: &(dyn std::any::Any + Send)) -> String {
+    cause
+        .downcast_ref::<&str>()
+        .map(|s| s.to_string())
+        .or_else(|| cause.downcast_ref::<String>().cloned())
+        .unwrap_or_default()
+}
+
+/// A level being generated on a worker thread.
+pub struct MapJob {
+    pub level: usize,
+    pub budget: Budget,
+    progress: Progress,
+    done: Arc<Mutex<Option<GeneratedLevel>>>,
+}
+
+impl MapJob {
+    pub fn spawn(request: MapRequest) -> Self {
+        let job = MapJob {
+            level: request.level,
+            budget: request.budget,
+            progress: Progress::default(),
+            done: Arc::default(),
+        };
+
+        let (budget, progress, done) = (job.budget, job.progress.clone(), job.done.clone());
+        thread::spawn(move || {
+            let fallback = request.clone();
+            let level =
+                panic::catch_unwind(AssertUnwindSafe(|| generate(request, budget, &progress)))
+                    .unwrap_or_else(|cause| {
+                        println!(
+                            "Generating level {} went wrong, using a plain room: {}",
+                            fallback.level,
+                            panic_cause(&*cause)
+                        );
+                        plain_level(&fallback, progress.attempts())
+                    });
+            *done.lock().unwrap() = Some(level);
+        });
+
+        job
+    }
+
+    pub fn attempts(&self) -> usize {
+        self.progress.attempts()
+    }
+
+    /// Takes the level if the worker is done, for callers polling once a frame.
+    pub fn try_take(&mut self) -> Option<GeneratedLevel> {
+        self.done.lock().unwrap().take()
+    }
+}
+
+fn generate(request: MapRequest, budget: Budget, progress: &Progress) -> GeneratedLevel {
+    if request.storeys > 1 {
+        return generate_storeys(request, budget, progress);
+    }
+
+    println!(
+        "Generating level {} with seed {}",
+        request.level,
+        request.seed.level(request.level)
+    );
+
+    let map_size = Vector3::new(request.width, request.height, 1);
+    let tile_size = Vector3::new(32, 32, 1);
+    let mut walls = TileMap::<RoomTile>::new(map_size, tile_size, None);
+    let mut floor = TileMap::<FloorTile>::new(map_size, tile_size, None);
+
+    let mut rng = request.seed.storey_rng(request.level, request.storey);
+    if let Generator::Surface { chunk_size } = request.generator {
+        return generate_surface(request, chunk_size, walls, floor, budget, &mut rng);
+    }
+
+    let exit = sanity_lib::mapgen::pick_exit(
+        request.width,
+        request.height,
+        request.start,
+        request.min_exit_distance,
+        &mut rng,
+    );
+    // only the wfc generator stamps vaults
+    let vaults = match request.generator {
+        Generator::Wfc => sanity_lib::mapgen::place_vaults(
+            &request.vaults,
+            request.width,
+            request.height,
+            &[request.start, exit],
+            &mut rng,
+        ),
+        Generator::Rooms { .. } | Generator::Surface { .. } => vec![],
+    };
+    let vault_exit = sanity_lib::mapgen::vault_exit(&vaults);
+    let exit = vault_exit.unwrap_or(exit);
+
+    let outcome = match request.generator {
+        Generator::Wfc => sanity_lib::mapgen::gen_map(
+            &mut walls,
+            &mut floor,
+            &request.pairs,
+            &request.model,
+            request.start,
+            exit,
+            &vaults,
+            budget,
+            progress,
+            &mut rng,
+        ),
+        Generator::Rooms { min_size, max_size } => sanity_lib::mapgen::gen_rooms(
+            &mut walls,
+            &mut floor,
+            &request.pairs,
+            &request.model,
+            request.start,
+            exit,
+            (min_size, max_size),
+            budget,
+            progress,
+            &mut rng,
+        ),
+        Generator::Surface { .. } => unreachable!("surface levels have their own generation"),
+    };
+    // the fallback room has no vaults in it
+    let spawns = match outcome {
+        Ok(_) => {
+            if let Some(floor_pairs) = &request.floor_pairs {
+                if let Err(e) = sanity_lib::mapgen::gen_floor(
+                    &mut walls,
+                    &mut floor,
+                    &request.pairs,
+                    floor_pairs,
+                    budget,
+                    &mut rng,
+                ) {
+                    println!("Leaving the floor plain: {}", e);
+                }
+            }
+            if request.secret_doors > 0 {
+                let doors = sanity_lib::mapgen::place_secret_doors(
+                    &mut walls,
+                    &request.pairs,
+                    request.secret_doors,
+                    &mut rng,
+                );
+                println!("Hid {} secret doors", doors.len());
+            }
+
+            vaults
+                .iter()
+                .flat_map(|v| v.spawns())
+                .filter(|(_, kind)| *kind != SpawnKind::Exit)
+                .collect()
+        }
+        Err(_) => {
+            sanity_lib::mapgen::fallback_map(&mut walls, &mut floor, &request.pairs);
+            vec![]
+        }
+    };
+
+    // an exit that wasn't put down by a vault is moved in to the nearest room
+    let regions = Regions::new(&walls);
+    let exit = match vault_exit {
+        Some(exit) => exit,
+        None => regions
+            .nearest_room(exit, request.start, request.min_exit_distance)
+            .unwrap_or(exit),
+    };
+
+    GeneratedLevel {
+        level: request.level,
+        walls,
+        floor,
+        exit,
+        spawns,
+        regions,
+        surface: None,
+        outcome,
+    }
+}
+
+/// Generates every storey of a level on its own and stacks them on one map, linked by ladders,
+/// lifts and holes. The player starts on the top storey and the exit is on the bottom one.
+fn generate_storeys(request: MapRequest, budget: Budget, progress: &Progress) -> GeneratedLevel {
+    let mut storeys: Vec<GeneratedLevel> = (0..request.storeys)
+        .map(|storey| {
+            let request = MapRequest {
+                storeys: 1,
+                storey,
+                ..request.clone()
+            };
+            generate(request, budget, progress)
+        })
+        .collect();
+
+    let map_size = Vector3::new(request.width, request.height, request.storeys);
+    let tile_size = Vector3::new(32, 32, 1);
+    let mut walls = TileMap::<RoomTile>::new(map_size, tile_size, None);
+    let mut floor = TileMap::<FloorTile>::new(map_size, tile_size, None);
+    for (z, storey) in storeys.iter().enumerate() {
+        for y in 0..request.height {
+            for x in 0..request.width {
+                let (from, to) = (Point3::new(x, y, 0), Point3::new(x, y, z as u32));
+                if let (Some(to), Some(from)) = (walls.get_mut(&to), storey.walls.get(&from)) {
+                    *to = from.clone();
+                }
+                if let (Some(to), Some(from)) = (floor.get_mut(&to), storey.floor.get(&from)) {
+                    *to = from.clone();
+                }
+            }
+        }
+    }
+
+    let exit = storeys[0].exit;
+    let mut rng = request.seed.storey_rng(request.level, request.storeys);
+    let links = sanity_lib::mapgen::link_storeys(
+        &mut walls,
+        &mut floor,
+        &request.pairs,
+        &[request.start, exit],
+        request.start,
+        3,
+        &mut rng,
+    );
+    println!(
+        "Linked {} storeys in {} places",
+        request.storeys,
+        links.len()
+    );
+
+    // spawns and regions are only used on the storey the player starts on
+    let mut top = storeys.pop().expect("More Than One Storey");
+    if let Some(failed) = storeys.into_iter().find(|s| s.outcome.is_err()) {
+        top.outcome = failed.outcome;
+    }
+
+    GeneratedLevel {
+        walls,
+        floor,
+        exit,
+        ..top
+    }
+}
+
+/// The chunks around the start of a surface level, the rest are generated as the player walks.
+fn generate_surface(
+    request: MapRequest,
+    chunk_size: u32,
+    mut walls: TileMap<RoomTile>,
+    mut floor: TileMap<FloorTile>,
+    budget: Budget,
+    rng: &mut StdRng,
+) -> GeneratedLevel {
+    let mut chunks = Chunks::new(request.pairs, chunk_size, request.seed.level(request.level));
+    let outcome =
+        sanity_lib::chunks::gen_surface(&mut walls, &mut floor, &mut chunks, request.start, budget);
+    let exit = sanity_lib::chunks::pick_exit(chunk_size, request.min_exit_distance, (-1, -1), rng);
+
+    GeneratedLevel {
+        level: request.level,
+        regions: Regions::new(&walls),
+        walls,
+        floor,
+        exit,
+        spawns: vec![],
+        surface: Some(chunks),
+        outcome,
+    }
+}
+
+/// A plain room on every storey, for when generating the level itself went wrong. The exit is
+/// in the corner furthest from the start.
+fn plain_level(request: &MapRequest, attempts: usize) -> GeneratedLevel {
+    let (storey_size, tile_size) = (
+        Vector3::new(request.width, request.height, 1),
+        Vector3::new(32, 32, 1),
+    );
+    let mut room = TileMap::<RoomTile>::new(storey_size, tile_size, None);
+    let mut room_floor = TileMap::<FloorTile>::new(storey_size, tile_size, None);
+    sanity_lib::mapgen::fallback_map(&mut room, &mut room_floor, &request.pairs);
+
+    let map_size = Vector3::new(request.width, request.height, request.storeys.max(1));
+    let mut walls = TileMap::<RoomTile>::new(map_size, tile_size, None);
+    let mut floor = TileMap::<FloorTile>::new(map_size, tile_size, None);
+    for z in 0..map_size.z {
+        for y in 0..request.height {
+            for x in 0..request.width {
+                let (from, to) = (Point3::new(x, y, 0), Point3::new(x, y, z));
+                if let (Some(to), Some(from)) = (walls.get_mut(&to), room.get(&from)) {
+                    *to = from.clone();
+                }
+                if let (Some(to), Some(from)) = (floor.get_mut(&to), room_floor.get(&from)) {
+                    *to = from.clone();
+                }
+            }
+        }
+    }
+
+    let (width, height) = (request.width as i32, request.height as i32);
+    let far = |start: i32, side: i32| if start < side / 2 { side - 2 } else { 1 };
+    let exit = Coord::new(far(request.start.x, width), far(request.start.y, height));
+
+    GeneratedLevel {
+        level: request.level,
+        regions: Regions::new(&room),
+        walls,
+        floor,
+        exit,
+        spawns: vec![],
+        surface: None,
+        outcome: Err(GenError {
+            attempts,
+            ..GenError::default()
+        }),
+    }
+}
+
+/// The ring of chunks just outside the window of a surface level, generated on a worker thread
+/// before the player walks up to it.
+pub struct ChunkJob {
+    done: Arc<Mutex<Option<Chunks>>>,
+}
+
+impl ChunkJob {
+    /// Generates the ring around `centre` on a copy of `chunks`, merge the copy back once done.
+    pub fn spawn(chunks: Chunks, centre: ChunkCoord, budget: Budget) -> Self {
+        let job = ChunkJob {
+            done: Arc::default(),
+        };
+
+        let done = job.done.clone();
+        thread::spawn(move || {
+            let before = chunks.clone();
+            let ahead = panic::catch_unwind(AssertUnwindSafe(|| {
+                let mut chunks = chunks;
+                for outcome in chunks.ring(centre, budget) {
+                    if let Err(e) = outcome {
+                        log::info!("Leaving a chunk open: {}", e);
+                    }
+                }
+                chunks
+            }))
+            .unwrap_or_else(|cause| {
+                // the chunks are generated on the main thread when the player gets there
+                log::info!(
+                    "Generating the chunks around {:?} went wrong: {}",
+                    centre,
+                    panic_cause(&*cause)
+                );
+                before
+            });
+            *done.lock().unwrap() = Some(ahead);
+        });
+
+        job
+    }
+
+    /// Takes the chunks if the worker is done, for callers polling once a frame.
+    pub fn try_take(&mut self) -> Option<Chunks> {
+        self.done.lock().unwrap().take()
+    }
+}
+
+/// Levels being generated in the background, keyed by depth.
+#[derive(Default)]
+pub struct Generation {
+    jobs: HashMap<usize, MapJob>,
+}
+
+impl Generation {
+    /// Starts generating a level unless it is already on its way.
+    pub fn request(&mut self, request: MapRequest) {
+        self.jobs
+            .entry(request.level)
+            .or_insert_with(|| MapJob::spawn(request));
+    }
+
+    pub fn get_mut(&mut self, level: usize) -> Option<&mut MapJob> {
+        self.jobs.get_mut(&level)
+    }
+
+    /// The finished level at `level`, the job is forgotten once it is taken.
+    pub fn take(&mut self, level: usize) -> Option<GeneratedLevel> {
+        let done = self.jobs.get_mut(&level)?.try_take();
+        if done.is_some() {
+            self.jobs.remove(&level);
+        }
+        done
+    }
+}
@@ -0,0 +1,102 @@
+use crate::component::item::ItemType;
+use amethyst::{
+    assets::{Asset, Handle},
+    ecs::VecStorage,
+    renderer::SpriteSheet,
+};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, num::NonZeroU32};
+
+/// An enemy that can appear on a level, picked by weight.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct EnemySpawn {
+    pub name: String,
+    pub health: u32,
+    pub weight: u32,
+}
+
+/// An item that can appear on a level, picked by weight.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ItemSpawn {
+    pub item: ItemType,
+    pub weight: u32,
+}
+
+/// How a single depth of the run is built.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct LevelDef {
+    pub size: (u32, u32), // unused by surface levels, their size comes from the chunk size
+    pub tileset: String,  // sprite sheet name, loaded from `<tileset>.png` and `<tileset>.ron`
+    pub pairs: String,
+    #[serde(default)]
+    pub floor_pairs: Option<String>, // decorates the floor after the walls are generated
+    pub enemies: Vec<EnemySpawn>,
+    pub items: Vec<ItemSpawn>,
+    pub max_enemies: usize,
+    pub max_items: usize,
+    #[serde(default)]
+    pub music: Option<String>, // keeps whatever is playing when not set
+    pub min_walkable_ratio: f32,
+    pub min_exit_distance: i32,
+    #[serde(default)]
+    pub vaults: Vec<String>, // placed in order, each at most once
+    #[serde(default)]
+    pub example: Option<String>, // hand painted map, `.ron` or a Tiled `.csv`, the rules are learned from
+    #[serde(default)]
+    pub pattern_size: Option<NonZeroU32>, // copy NxN patterns of the example, otherwise only learn its pairs
+    #[serde(default)]
+    pub weights: HashMap<usize, u32>, // sprite to generation weight on this depth, over the pairs file
+    #[serde(default)]
+    pub generator: sanity_lib::mapgen::Generator, // `Wfc` over the whole map, `Rooms(..)` or `Surface(..)`
+    #[serde(default)]
+    pub quality: sanity_lib::metrics::Thresholds, // layouts outside of these are generated again
+    #[serde(default)]
+    pub storeys: u32, // stacked on one map and linked by ladders, lifts and holes, 1 when not set
+    #[serde(default)]
+    pub secret_doors: usize, // walls hiding short cuts, found by searching next to them
+}
+
+impl LevelDef {
+    /// Storeys the level is built of, surface levels only ever have one.
+    pub fn storeys(&self) -> u32 {
+        match self.generator {
+            sanity_lib::mapgen::Generator::Surface { .. } => 1,
+            _ => self.storeys.max(1),
+        }
+    }
+}
+
+/// Every depth of the run in order, loaded from `levels.ron`.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Levels {
+    pub levels: Vec<LevelDef>,
+}
+
+impl Levels {
+    /// Definition for a depth, levels are counted from 1.
+    pub fn get(&self, level: usize) -> Option<&LevelDef> {
+        level.checked_sub(1).and_then(|idx| self.levels.get(idx))
+    }
+
+    /// The deepest level, it has no exit.
+    pub fn last(&self) -> usize {
+        self.levels.len()
+    }
+}
+
+pub type LevelsHandle = Handle<Levels>;
+
+impl Asset for Levels {
+    const NAME: &'static str = "sanity::resource::Levels";
+    type Data = Self;
+    type HandleStorage = VecStorage<LevelsHandle>;
+}
+
+/// Sprite sheets, pairs, vaults and examples used by the levels, keyed by the names in `levels.ron`.
+#[derive(Default)]
+pub struct Tilesets {
+    pub sheets: HashMap<String, Handle<SpriteSheet>>,
+    pub pairs: HashMap<String, Handle<sanity_lib::assets::Pairs>>,
+    pub vaults: HashMap<String, Handle<sanity_lib::assets::Vault>>,
+    pub examples: HashMap<String, Handle<sanity_lib::assets::Example>>,
+}
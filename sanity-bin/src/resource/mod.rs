@@ -5,6 +5,22 @@ use amethyst::{
     renderer::{ImageFormat, SpriteRender, SpriteSheet, SpriteSheetFormat, Texture},
 };
 
+mod dijkstra;
+mod generation;
+mod levels;
+mod seed;
+mod settings;
+mod surface;
+mod visited;
+
+pub use dijkstra::{Built, DijkstraMaps, Purpose};
+pub use generation::{ChunkJob, GeneratedLevel, Generation, MapRequest};
+pub use levels::{EnemySpawn, ItemSpawn, LevelDef, Levels, LevelsHandle, Tilesets};
+pub use seed::{GameRng, Seed};
+pub use settings::Settings;
+pub use surface::Surface;
+pub use visited::{StoredLevel, VisitedLevels};
+
 pub fn load_sprite_sheet(
     world: &World,
     png_path: &str,
@@ -56,11 +72,13 @@ impl Sprited<()> for Bullets {
 
 pub struct Exits {
     pub sheet: Handle<SpriteSheet>,
+    pub sprite: usize,
+    pub stairs_up: usize,
 }
 
 impl Sprited<()> for Exits {
     fn new_sprite(&self, _: ()) -> SpriteRender {
-        SpriteRender::new(self.sheet.clone(), 227)
+        SpriteRender::new(self.sheet.clone(), self.sprite)
     }
 }
 
@@ -0,0 +1,89 @@
+use rand::{rngs::StdRng, SeedableRng};
+
+/// The run seed every random roll in the game is derived from.
+#[derive(Clone, Copy, Debug)]
+pub struct Seed(pub u64);
+
+impl Default for Seed {
+    fn default() -> Self {
+        Seed(rand::random())
+    }
+}
+
+impl Seed {
+    /// Reads `--seed <n>` from the command line, falling back to a random seed.
+    pub fn from_args() -> Self {
+        std::env::args()
+            .skip_while(|arg| arg != "--seed")
+            .nth(1)
+            .and_then(|arg| arg.parse().ok())
+            .map(Seed)
+            .unwrap_or_default()
+    }
+
+    /// Seed for the level at `depth`, so a level can be regenerated without replaying the run.
+    pub fn level(&self, depth: usize) -> u64 {
+        splitmix64(self.0 ^ splitmix64(depth as u64))
+    }
+
+    /// Rng used to generate the layout of the level at `depth`.
+    pub fn map_rng(&self, depth: usize) -> StdRng {
+        StdRng::seed_from_u64(self.level(depth))
+    }
+
+    /// Rng used to generate one storey of the level at `depth`, the bottom one uses `map_rng`.
+    pub fn storey_rng(&self, depth: usize, storey: u32) -> StdRng {
+        match storey {
+            0 => self.map_rng(depth),
+            _ => StdRng::seed_from_u64(splitmix64(self.level(depth) ^ storey as u64)),
+        }
+    }
+
+    /// Rng used for spawns, damage and AI on the level at `depth`.
+    pub fn level_rng(&self, depth: usize) -> StdRng {
+        StdRng::seed_from_u64(splitmix64(self.level(depth)))
+    }
+}
+
+/// Shared rng for systems, reseeded from the `Seed` whenever a level starts.
+pub struct GameRng(pub StdRng);
+
+impl Default for GameRng {
+    fn default() -> Self {
+        GameRng(Seed::default().level_rng(0))
+    }
+}
+
+fn splitmix64(x: u64) -> u64 {
+    let mut z = x.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::Rng;
+
+    fn rolls(mut rng: StdRng) -> Vec<u64> {
+        (0..8).map(|_| rng.gen()).collect()
+    }
+
+    #[test]
+    fn same_seed_rolls_the_same() {
+        let (run, again) = (Seed(42), Seed(42));
+        assert_eq!(rolls(run.map_rng(3)), rolls(again.map_rng(3)));
+        assert_eq!(rolls(run.level_rng(3)), rolls(again.level_rng(3)));
+        assert_eq!(rolls(run.storey_rng(3, 1)), rolls(again.storey_rng(3, 1)));
+    }
+
+    #[test]
+    fn streams_differ_by_seed_depth_and_use() {
+        let run = Seed(42);
+        assert_ne!(rolls(run.map_rng(3)), rolls(Seed(43).map_rng(3)));
+        assert_ne!(rolls(run.map_rng(3)), rolls(run.map_rng(4)));
+        assert_ne!(rolls(run.map_rng(3)), rolls(run.level_rng(3)));
+        assert_eq!(rolls(run.map_rng(3)), rolls(run.storey_rng(3, 0)));
+    }
+}
@@ -0,0 +1,19 @@
+use sanity_lib::map::Moves;
+
+/// Options picked for the whole run.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Settings {
+    pub moves: Moves,
+}
+
+impl Settings {
+    /// Reads `--diagonal` from the command line, which turns on 8-way movement.
+    pub fn from_args() -> Self {
+        let moves = if std::env::args().any(|arg| arg == "--diagonal") {
+            Moves::Diagonal
+        } else {
+            Moves::Cardinal
+        };
+        Settings { moves }
+    }
+}
@@ -0,0 +1,13 @@
+use super::ChunkJob;
+use sanity_lib::{
+    chunks::{ChunkCoord, Chunks},
+    mapgen::Budget,
+};
+
+/// The surface level being played, its tilemaps show the chunks `WINDOW` across from `origin`.
+pub struct Surface {
+    pub chunks: Chunks,
+    pub origin: ChunkCoord,      // chunk at the top left of the tilemaps
+    pub budget: Budget,          // chunks the worker didn't get to are generated between frames
+    pub ahead: Option<ChunkJob>, // the chunks around the window being generated on a worker
+}
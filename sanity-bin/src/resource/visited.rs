@@ -0,0 +1,29 @@
+use super::Surface;
+use amethyst::ecs::Entity;
+use std::collections::HashMap;
+
+/// A level the player has left, its tilemaps and everything on them hidden until they are back.
+pub struct StoredLevel {
+    pub walls: Entity,
+    pub floors: Entity,
+    pub surface: Option<Surface>, // the chunks of a surface level
+}
+
+/// Every level the player has left, by depth.
+#[derive(Default)]
+pub struct VisitedLevels(HashMap<usize, StoredLevel>);
+
+impl VisitedLevels {
+    pub fn insert(&mut self, level: usize, stored: StoredLevel) {
+        self.0.insert(level, stored);
+    }
+
+    /// Takes the level back out to be played again.
+    pub fn take(&mut self, level: usize) -> Option<StoredLevel> {
+        self.0.remove(&level)
+    }
+
+    pub fn contains(&self, level: usize) -> bool {
+        self.0.contains_key(&level)
+    }
+}
@@ -4,7 +4,7 @@ use amethyst::{
     input::{is_close_requested, is_key_down},
     prelude::*,
     ui::UiCreator,
-    ui::UiFinder,
+    ui::{UiFinder, UiText},
     winit,
 };
 
@@ -13,6 +13,7 @@ use crate::gamedata::CustomGameData;
 #[derive(Default)]
 pub struct GameOverState {
     message: Option<Entity>,
+    seed_shown: bool,
 }
 
 impl<'a, 'b> State<crate::gamedata::CustomGameData<'a, 'b>, StateEvent> for GameOverState {
@@ -35,6 +36,27 @@ impl<'a, 'b> State<crate::gamedata::CustomGameData<'a, 'b>, StateEvent> for Game
     ) -> Trans<CustomGameData<'a, 'b>, StateEvent> {
         data.data.update(&data.world, false);
 
+        if !self.seed_shown {
+            data.world.exec(
+                |(finder, mut ui_text, seed, sanity_res): (
+                    UiFinder<'_>,
+                    WriteStorage<'_, UiText>,
+                    Read<'_, crate::resource::Seed>,
+                    Read<'_, crate::state::Sanity>,
+                )| {
+                    if let Some(message) = finder.find("gameover").and_then(|e| ui_text.get_mut(e))
+                    {
+                        message.text = format!(
+                            "Game Over\n\nSeed {} Level {}\n\nPress R to restart current level.",
+                            seed.0,
+                            sanity_res.level.len()
+                        );
+                        self.seed_shown = true;
+                    }
+                },
+            );
+        }
+
         Trans::None
     }
 
@@ -1,11 +1,11 @@
 use amethyst::{
     assets::{AssetStorage, Handle, Loader, Prefab, ProgressCounter, RonFormat},
     core::Named,
-    ecs::{Entities, Entity},
+    ecs::{Entities, Entity, WriteStorage},
     input::{is_close_requested, is_key_down},
     prelude::*,
     renderer::SpriteSheet,
-    ui::UiCreator,
+    ui::{LineMode, UiCreator, UiFinder, UiText},
     winit,
 };
 
@@ -17,9 +17,146 @@ use super::RoomState;
 pub struct LoadingState {
     progress_counter: ProgressCounter,
     player: Option<Handle<Prefab<AnimatedSpritePrefab>>>,
-    map: Option<Handle<SpriteSheet>>,
-    pairs: Option<Handle<sanity_lib::assets::Pairs>>,
+    levels: Option<Handle<crate::resource::Levels>>,
+    tilesets_requested: bool,
     loading: Option<Entity>,
+    failed: bool, // a pairs file has errors, the message stays up until the game is closed
+}
+
+impl LoadingState {
+    /// Loads the sprite sheet, pairs, vaults and example of every level, each only once.
+    fn load_tilesets(&mut self, world: &mut World) {
+        let levels = world
+            .read_resource::<AssetStorage<crate::resource::Levels>>()
+            .get(self.levels.as_ref().expect("Levels Loaded"))
+            .cloned()
+            .expect("Levels Loaded");
+
+        let mut tilesets = crate::resource::Tilesets::default();
+        for def in &levels.levels {
+            if !tilesets.sheets.contains_key(&def.tileset) {
+                let sheet = crate::resource::load_sprite_sheet(
+                    &world,
+                    &format!("{}.png", def.tileset),
+                    &format!("{}.ron", def.tileset),
+                    &mut self.progress_counter,
+                );
+                tilesets.sheets.insert(def.tileset.clone(), sheet);
+            }
+
+            for pairs_file in Some(&def.pairs).into_iter().chain(&def.floor_pairs) {
+                if !tilesets.pairs.contains_key(pairs_file) {
+                    let loader = world.read_resource::<Loader>();
+                    let pairs = loader.load(
+                        pairs_file.as_str(),
+                        RonFormat,
+                        &mut self.progress_counter,
+                        &world.read_resource::<AssetStorage<sanity_lib::assets::Pairs>>(),
+                    );
+                    tilesets.pairs.insert(pairs_file.clone(), pairs);
+                }
+            }
+
+            for vault in &def.vaults {
+                if !tilesets.vaults.contains_key(vault) {
+                    let loader = world.read_resource::<Loader>();
+                    let handle = loader.load(
+                        vault.as_str(),
+                        RonFormat,
+                        &mut self.progress_counter,
+                        &world.read_resource::<AssetStorage<sanity_lib::assets::Vault>>(),
+                    );
+                    tilesets.vaults.insert(vault.clone(), handle);
+                }
+            }
+
+            if let Some(example) = &def.example {
+                if !tilesets.examples.contains_key(example) {
+                    let loader = world.read_resource::<Loader>();
+                    let storage =
+                        world.read_resource::<AssetStorage<sanity_lib::assets::Example>>();
+                    let handle = if example.ends_with(".csv") {
+                        let format = sanity_lib::assets::TiledCsvFormat;
+                        loader.load(
+                            example.as_str(),
+                            format,
+                            &mut self.progress_counter,
+                            &storage,
+                        )
+                    } else {
+                        loader.load(
+                            example.as_str(),
+                            RonFormat,
+                            &mut self.progress_counter,
+                            &storage,
+                        )
+                    };
+                    tilesets.examples.insert(example.clone(), handle);
+                }
+            }
+        }
+
+        let first = levels.get(1).expect("levels.ron has at least one level");
+        world.insert(crate::resource::Exits {
+            sheet: tilesets.sheets[&first.tileset].clone(),
+            sprite: 0, // taken from the pairs of each level as it starts
+            stairs_up: 0,
+        });
+        world.insert(tilesets);
+        world.insert(levels);
+        world.insert(crate::resource::VisitedLevels::default());
+        world.insert(crate::resource::DijkstraMaps::default());
+        self.tilesets_requested = true;
+    }
+
+    /// Checks the pairs and floor pairs of every level against its sprite sheet, the errors of
+    /// the first broken one.
+    fn lint_tilesets(&self, world: &World) -> Option<String> {
+        let levels = world.read_resource::<crate::resource::Levels>();
+        let tilesets = world.read_resource::<crate::resource::Tilesets>();
+        let pairs_storage = world.read_resource::<AssetStorage<sanity_lib::assets::Pairs>>();
+        let sheets = world.read_resource::<AssetStorage<SpriteSheet>>();
+
+        let mut checked = vec![];
+        for def in &levels.levels {
+            let floor_pairs = def.floor_pairs.iter().map(|f| (f, true));
+            for (pairs_file, floor) in Some((&def.pairs, false)).into_iter().chain(floor_pairs) {
+                if checked.contains(&(pairs_file, &def.tileset)) {
+                    continue;
+                }
+                checked.push((pairs_file, &def.tileset));
+
+                let pairs = match pairs_storage.get(&tilesets.pairs[pairs_file]) {
+                    Some(pairs) => pairs,
+                    None => continue,
+                };
+                let sprites = sheets
+                    .get(&tilesets.sheets[&def.tileset])
+                    .map(|sheet| sheet.sprites.len());
+                // floor pairs only decorate the level the walls were generated for
+                let problems = if floor {
+                    sanity_lib::lint::lint_floor(pairs, sprites)
+                } else {
+                    sanity_lib::lint::lint(pairs, sprites)
+                };
+
+                for problem in &problems {
+                    log::warn!("{} with {}: {}", pairs_file, def.tileset, problem);
+                }
+
+                let errors: Vec<String> = problems
+                    .iter()
+                    .filter(|p| p.is_error())
+                    .map(|p| p.to_string())
+                    .collect();
+                if !errors.is_empty() {
+                    return Some(format!("{} is broken:\n{}", pairs_file, errors.join("\n")));
+                }
+            }
+        }
+
+        None
+    }
 }
 
 impl<'a, 'b> State<crate::gamedata::CustomGameData<'a, 'b>, StateEvent> for LoadingState {
@@ -29,9 +166,14 @@ impl<'a, 'b> State<crate::gamedata::CustomGameData<'a, 'b>, StateEvent> for Load
         // register components, may be able to remove if used by system
         world.register::<Named>();
         world.register::<Handle<sanity_lib::assets::Pairs>>();
+        world.register::<Handle<sanity_lib::assets::Vault>>();
+        world.register::<Handle<sanity_lib::assets::Example>>();
         world.register::<crate::component::Item>();
+        world.register::<crate::component::Regions>();
+        world.register::<crate::component::StairsUp>();
 
         world.insert(crate::state::Sanity::default());
+        world.insert(crate::resource::Generation::default());
 
         // insert resources in to world
         world.insert(crate::resource::Bullets {
@@ -52,15 +194,6 @@ impl<'a, 'b> State<crate::gamedata::CustomGameData<'a, 'b>, StateEvent> for Load
             ),
         });
 
-        world.insert(crate::resource::Exits {
-            sheet: crate::resource::load_sprite_sheet(
-                &world,
-                "Dungeon_Tileset.png",
-                "Dungeon_Tileset.ron",
-                &mut self.progress_counter,
-            ),
-        });
-
         let anims = crate::resource::load_anim_prefab(
             &mut world,
             "sprites/slime.anim.ron",
@@ -68,27 +201,20 @@ impl<'a, 'b> State<crate::gamedata::CustomGameData<'a, 'b>, StateEvent> for Load
         );
         world.insert(crate::resource::Enemies { anims });
 
-        self.map = Some(crate::resource::load_sprite_sheet(
-            &world,
-            "Dungeon_Tileset.png",
-            "Dungeon_Tileset.ron",
-            &mut self.progress_counter,
-        ));
-
         self.player = Some(crate::resource::load_anim_prefab(
             world,
             "sprites/Space Cadet.anim.ron",
             &mut self.progress_counter,
         ));
 
-        // load the tile pairs for this tileset
-        self.pairs = {
+        // tilesets are loaded once we know which ones the levels use
+        self.levels = {
             let loader = world.read_resource::<Loader>();
             Some(loader.load(
-                "Dungeon_Tileset.pairs.ron",
+                "levels.ron",
                 RonFormat,
                 &mut self.progress_counter,
-                &world.read_resource::<AssetStorage<sanity_lib::assets::Pairs>>(),
+                &world.read_resource::<AssetStorage<crate::resource::Levels>>(),
             ))
         };
 
@@ -106,24 +232,37 @@ impl<'a, 'b> State<crate::gamedata::CustomGameData<'a, 'b>, StateEvent> for Load
 
         data.data.update(world, false);
 
-        if self.progress_counter.is_complete() {
+        if self.failed {
+            Trans::None
+        } else if self.progress_counter.is_complete() && !self.tilesets_requested {
+            self.load_tilesets(world);
+            Trans::None
+        } else if self.progress_counter.is_complete() {
+            if let Some(error) = self.lint_tilesets(world) {
+                self.failed = true;
+                world.exec(
+                    |(finder, mut ui_text): (UiFinder<'_>, WriteStorage<'_, UiText>)| {
+                        if let Some(label) =
+                            finder.find("loading_text").and_then(|e| ui_text.get_mut(e))
+                        {
+                            label.text = error;
+                            label.font_size = 20.;
+                            label.line_mode = LineMode::Wrap;
+                        }
+                    },
+                );
+                return Trans::None;
+            }
+
             world.exec(|entities: Entities<'_>| {
                 entities.delete(self.loading.unwrap());
             });
 
-            Trans::Push(Box::new(RoomState {
-                camera: None,
-                level: 1,
-                width: crate::state::room::LEVEL_SIZES[0].0,
-                height: crate::state::room::LEVEL_SIZES[0].1,
-                player_anim: self.player.take().expect("Player Loaded"),
-                map_spritesheet: self.map.take().expect("Map Loaded"),
-                pairs: self.pairs.take().expect("Pairs Loaded"),
-                player: None,
-                walls: None,
-                floors: None,
-                hud: None,
-            }))
+            Trans::Push(Box::new(RoomState::for_level(
+                world,
+                1,
+                self.player.take().expect("Player Loaded"),
+            )))
         } else {
             Trans::None
         }
@@ -9,10 +9,14 @@ pub use loading::LoadingState;
 pub use room::RoomState;
 
 use amethyst::ecs::prelude::*;
+use bracket_pathfinding::prelude::Point;
+use sanity_lib::assets::SpawnKind;
 
 #[derive(Default)]
 pub struct Sanity {
     pub game_over: bool,
     pub level: Vec<Option<Entity>>,
     pub floor: Vec<Option<Entity>>,
+    pub notice: Option<String>, // shown on the hud, e.g. when map generation failed
+    pub spawn_points: Vec<(Point, SpawnKind)>, // vault spawns waiting for the spawn system
 }
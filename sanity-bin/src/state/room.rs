@@ -8,35 +8,75 @@ use amethyst::{
     ecs::prelude::*,
     input::{is_close_requested, is_key_down},
     prelude::*,
-    renderer::{camera::Camera, SpriteSheet, Transparent},
-    tiles::{Map, TileMap},
-    ui::UiCreator,
+    renderer::{camera::Camera, SpriteRender, SpriteSheet, Transparent},
+    tiles::{Map, MapStorage, TileMap},
+    ui::{UiCreator, UiFinder, UiText},
     window::ScreenDimensions,
     winit,
 };
 use bracket_pathfinding::prelude::Point;
-use sanity_lib::tile::{FloorTile, RoomTile};
-
-pub static LEVEL_SIZES: &'static [(u32, u32)] =
-    &[(12, 12), (24, 24), (48, 32), (32, 48), (64, 64), (8, 8)];
+use direction::Coord;
+use sanity_lib::{
+    regions::TileKind,
+    tile::{FloorTile, RoomTile},
+};
 
 pub struct RoomState {
     pub level: usize,
     pub width: u32,
     pub height: u32,
+    pub storeys: u32,
     pub pairs: Handle<sanity_lib::assets::Pairs>,
     pub camera: Option<Entity>,
     pub walls: Option<Entity>,
     pub floors: Option<Entity>,
     pub player: Option<Entity>,
     pub hud: Option<Entity>,
+    pub loading: Option<Entity>, // overlay shown while the level is generated
     pub player_anim: Handle<Prefab<AnimatedSpritePrefab>>,
     pub map_spritesheet: Handle<SpriteSheet>,
 }
 
 impl RoomState {
+    /// A room built from the definition of `level` in `levels.ron`.
+    pub fn for_level(
+        world: &World,
+        level: usize,
+        player_anim: Handle<Prefab<AnimatedSpritePrefab>>,
+    ) -> Self {
+        let levels = world.read_resource::<crate::resource::Levels>();
+        let tilesets = world.read_resource::<crate::resource::Tilesets>();
+        let def = levels.get(level).expect("Level Defined");
+
+        let (width, height) = def.generator.map_size(def.size);
+
+        RoomState {
+            level,
+            width,
+            height,
+            storeys: def.storeys(),
+            pairs: tilesets.pairs[&def.pairs].clone(),
+            map_spritesheet: tilesets.sheets[&def.tileset].clone(),
+            player_anim,
+            camera: None,
+            walls: None,
+            floors: None,
+            player: None,
+            hud: None,
+            loading: None,
+        }
+    }
+
+    /// Where the player comes in to the level, the middle of its top storey.
+    fn start(&self) -> (Point, u32) {
+        (
+            Point::new(self.width / 2, self.height / 2),
+            self.storeys - 1,
+        )
+    }
+
     fn init_map(&mut self, world: &mut World) {
-        let map_size = Vector3::new(self.width, self.height, 1);
+        let map_size = Vector3::new(self.width, self.height, self.storeys);
         let tile_size = Vector3::new(32, 32, 1);
 
         self.floors = Some(
@@ -83,7 +123,7 @@ impl RoomState {
         );
     }
 
-    fn init_player(&mut self, world: &mut World, pos: Point) {
+    fn init_player(&mut self, world: &mut World, (pos, z): (Point, u32)) {
         let weapon = world
             .create_entity()
             .with(crate::component::Weapon {
@@ -111,6 +151,7 @@ impl RoomState {
                 .with(crate::component::Position {
                     pos,
                     map: self.walls.unwrap(),
+                    z,
                 })
                 .with(self.player_anim.clone())
                 .with(t)
@@ -118,36 +159,437 @@ impl RoomState {
         );
     }
 
-    fn gen_map_exec(&self, world: &mut World) {
-        // delete all the enemies so they respawn
+    /// Queues generation of the level at `level` unless it is already underway.
+    fn request_level(&self, world: &World, level: usize) {
+        let def = world
+            .read_resource::<crate::resource::Levels>()
+            .get(level)
+            .cloned()
+            .expect("Level Defined");
+        let (width, height) = def.generator.map_size(def.size);
+        let pairs = world
+            .read_resource::<AssetStorage<sanity_lib::assets::Pairs>>()
+            .get(&world.read_resource::<crate::resource::Tilesets>().pairs[&def.pairs])
+            .cloned()
+            .expect("Pairs Loaded");
+        let floor_pairs = def.floor_pairs.as_ref().and_then(|f| {
+            world
+                .read_resource::<AssetStorage<sanity_lib::assets::Pairs>>()
+                .get(&world.read_resource::<crate::resource::Tilesets>().pairs[f])
+                .cloned()
+        });
+        let seed = *world.read_resource::<crate::resource::Seed>();
+        let vaults = {
+            let tilesets = world.read_resource::<crate::resource::Tilesets>();
+            let storage = world.read_resource::<AssetStorage<sanity_lib::assets::Vault>>();
+            def.vaults
+                .iter()
+                .filter_map(|v| storage.get(&tilesets.vaults[v]).cloned())
+                .collect()
+        };
+        let example = def.example.as_ref().and_then(|e| {
+            world
+                .read_resource::<AssetStorage<sanity_lib::assets::Example>>()
+                .get(&world.read_resource::<crate::resource::Tilesets>().examples[e])
+                .cloned()
+        });
+        // an example replaces the hand picked pairs with the ones it shows
+        let (pairs, model) = match (example, def.pattern_size) {
+            (Some(example), Some(size)) => (
+                example.learn(&pairs),
+                sanity_lib::mapgen::Model::Overlapping { example, size },
+            ),
+            (Some(example), None) => (example.learn(&pairs), sanity_lib::mapgen::Model::Pairs),
+            (None, _) => (pairs, sanity_lib::mapgen::Model::Pairs),
+        };
+        let mut pairs = pairs;
+        for (sprite, weight) in &def.weights {
+            pairs.tiles.entry(*sprite).or_default().weight = Some(*weight);
+        }
+
+        world
+            .write_resource::<crate::resource::Generation>()
+            .request(crate::resource::MapRequest {
+                level,
+                width,
+                height,
+                start: Coord::new(width as i32 / 2, height as i32 / 2),
+                min_exit_distance: def.min_exit_distance,
+                budget: sanity_lib::mapgen::Budget {
+                    min_walkable_ratio: def.min_walkable_ratio,
+                    thresholds: def.quality,
+                    ..Default::default()
+                },
+                pairs,
+                model,
+                generator: def.generator,
+                floor_pairs,
+                vaults,
+                seed,
+                storeys: def.storeys(),
+                storey: 0,
+                secret_doors: def.secret_doors,
+            });
+    }
+
+    /// Shows the loading overlay until the level at `self.level` is generated,
+    /// which is right away when it was generated ahead of time.
+    fn begin_level(&mut self, world: &mut World) {
+        self.request_level(world, self.level);
+        world.exec(|mut creator: UiCreator<'_>| {
+            self.loading = Some(creator.create("ui/loading.ron", ()));
+        });
+        self.poll_level(world);
+    }
+
+    /// Checks on the level being generated, returns true once it is in place.
+    fn poll_level(&mut self, world: &mut World) -> bool {
+        let (generated, attempts, max_attempts) = {
+            let mut generation = world.write_resource::<crate::resource::Generation>();
+            let (attempts, max_attempts) = generation
+                .get_mut(self.level)
+                .map_or((0, 0), |job| (job.attempts(), job.budget.max_attempts));
+            (generation.take(self.level), attempts, max_attempts)
+        };
+
+        match generated {
+            Some(generated) => {
+                self.apply_level(world, generated);
+                if let Some(loading) = self.loading.take() {
+                    world.exec(|entities: Entities<'_>| {
+                        entities.delete(loading);
+                    });
+                }
+
+                // get a head start on the level below while this one is played
+                let next = self.level + 1;
+                if next <= world.read_resource::<crate::resource::Levels>().last()
+                    && !world
+                        .read_resource::<crate::resource::VisitedLevels>()
+                        .contains(next)
+                {
+                    self.request_level(world, self.level + 1);
+                }
+                true
+            }
+            None => {
+                let text = format!(
+                    "Generating level {}\nattempt {}/{}",
+                    self.level, attempts, max_attempts
+                );
+                world.exec(
+                    |(finder, mut ui_text): (UiFinder<'_>, WriteStorage<'_, UiText>)| {
+                        if let Some(label) =
+                            finder.find("loading_text").and_then(|e| ui_text.get_mut(e))
+                        {
+                            label.text = text;
+                        }
+                    },
+                );
+                false
+            }
+        }
+    }
+
+    fn apply_level(&self, world: &mut World, generated: crate::resource::GeneratedLevel) {
+        let def = world
+            .read_resource::<crate::resource::Levels>()
+            .get(self.level)
+            .cloned()
+            .expect("Level Defined");
+        if let Some(music) = &def.music {
+            crate::audio::play_music(world, music);
+        }
+
+        let (exit_sprite, stairs_sprite) = world
+            .read_resource::<AssetStorage<sanity_lib::assets::Pairs>>()
+            .get(&self.pairs)
+            .map_or((0, 0), |pairs| {
+                (
+                    pairs.exit().unwrap_or_default(),
+                    pairs.stairs_up().unwrap_or_default(),
+                )
+            });
+        {
+            let mut exits = world.write_resource::<crate::resource::Exits>();
+            exits.sheet = self.map_spritesheet.clone();
+            exits.sprite = exit_sprite;
+            exits.stairs_up = stairs_sprite;
+        }
+
+        // delete all the enemies of this level so they respawn
+        let map = self.walls.unwrap();
         world.exec(
-            |(entities, enemies): (Entities<'_>, ReadStorage<'_, crate::component::Enemy>)| {
-                for (entity, _enemy) in (&entities, &enemies).join() {
+            |(entities, enemies, positions): (
+                Entities<'_>,
+                ReadStorage<'_, crate::component::Enemy>,
+                ReadStorage<'_, crate::component::Position>,
+            )| {
+                for (entity, _enemy, position) in (&entities, &enemies, &positions).join() {
+                    if position.map != map {
+                        continue;
+                    }
                     entities.delete(entity);
                 }
             },
         );
 
-        world.exec(
-            |(mut wall_maps, mut floor_maps, assets, players, positions): (
+        let exit = world.exec(
+            |(mut wall_maps, mut floor_maps, seed, mut game_rng, mut sanity_res): (
                 WriteStorage<'_, TileMap<RoomTile>>,
                 WriteStorage<'_, TileMap<FloorTile>>,
-                Read<'_, AssetStorage<sanity_lib::assets::Pairs>>,
-                ReadStorage<'_, crate::component::Player>,
-                ReadStorage<'_, crate::component::Position>,
+                Read<'_, crate::resource::Seed>,
+                Write<'_, crate::resource::GameRng>,
+                Write<'_, crate::state::Sanity>,
             )| {
+                game_rng.0 = seed.level_rng(self.level);
+
                 let floor = floor_maps.get_mut(self.floors.unwrap()).unwrap();
                 let walls = wall_maps.get_mut(self.walls.unwrap()).unwrap();
-                for (_, pos) in (&players, &positions).join() {
-                    crate::map::gen_map(
-                        walls,
-                        floor,
-                        assets.get(&self.pairs.clone()).unwrap(),
-                        pos.coord(),
-                    );
+
+                for z in 0..self.storeys {
+                    for x in 0..self.width {
+                        for y in 0..self.height {
+                            let p = Point3::new(x, y, z);
+                            if let (Some(to), Some(from)) =
+                                (walls.get_mut(&p), generated.walls.get(&p))
+                            {
+                                *to = from.clone();
+                            }
+                            if let (Some(to), Some(from)) =
+                                (floor.get_mut(&p), generated.floor.get(&p))
+                            {
+                                *to = from.clone();
+                            }
+                        }
+                    }
+                }
+
+                sanity_res.notice = None;
+                sanity_res.spawn_points = generated
+                    .spawns
+                    .iter()
+                    .map(|(at, kind)| (Point::new(at.x, at.y), *kind))
+                    .collect();
+                match &generated.outcome {
+                    Ok(stats) => println!("Generated level {} {:?}", self.level, stats),
+                    Err(e) => {
+                        println!("{}, falling back to an empty room", e);
+                        sanity_res.notice = Some(format!(
+                            "Map generation failed, best walkable {:.0}%",
+                            e.best_walkable_ratio * 100.
+                        ));
+                    }
+                }
+
+                // counted from the first tile, a surface exit can be off the tilemaps
+                let mut t = Transform::from(walls.to_world(&Point3::new(0, 0, 0), None));
+                t.prepend_translation_x(generated.exit.x as f32 * walls.tile_dimensions().x as f32);
+                t.prepend_translation_y(
+                    -generated.exit.y as f32 * walls.tile_dimensions().y as f32,
+                );
+                t.move_up(8.);
+
+                let (at, z) = self.start();
+                let mut stairs = Transform::from(
+                    walls.to_world(&Point3::new(at.x as u32, at.y as u32, z), None),
+                );
+                stairs.move_up(8.);
+                ((Point::new(generated.exit.x, generated.exit.y), t), stairs)
+            },
+        );
+        let (exit, stairs) = exit;
+        // a restart regenerates the same map under the player
+        world
+            .write_resource::<crate::resource::DijkstraMaps>()
+            .invalidate();
+
+        // enemies keep out of the corridors around the start
+        let start = Coord::new(self.width as i32 / 2, self.height as i32 / 2);
+        let guarded = generated
+            .regions
+            .around(start, 2)
+            .into_iter()
+            .filter(|id| generated.regions.regions[*id].kind != TileKind::Room)
+            .collect();
+        world
+            .write_storage::<crate::component::Regions>()
+            .insert(
+                self.walls.unwrap(),
+                crate::component::Regions {
+                    regions: generated.regions,
+                    guarded,
+                    storey: self.storeys - 1,
+                },
+            )
+            .expect("Walls Exist");
+
+        // surface levels keep their chunks to generate more as the player walks
+        match generated.surface {
+            Some(chunks) => world.insert(crate::resource::Surface {
+                chunks,
+                origin: (-1, -1),
+                budget: sanity_lib::mapgen::Budget {
+                    max_attempts: 10,
+                    time_limit: std::time::Duration::from_millis(200),
+                    ..Default::default()
+                },
+                ahead: None,
+            }),
+            None => {
+                world.remove::<crate::resource::Surface>();
+            }
+        }
+
+        // the last level has no way further down
+        if self.level < world.read_resource::<crate::resource::Levels>().last() {
+            let (pos, transform) = exit;
+            let sprite = world
+                .read_resource::<crate::resource::Exits>()
+                .new_sprite(());
+            world
+                .create_entity()
+                .with(crate::component::Exit)
+                .with(Hidden)
+                .with(crate::component::Position {
+                    pos,
+                    map: self.walls.unwrap(),
+                    z: 0, // always on the bottom storey
+                })
+                .with(transform)
+                .with(sprite)
+                .build();
+        }
+
+        // every level below the first has a way back up where the player came in
+        if self.level > 1 {
+            let sprite = {
+                let exits = world.read_resource::<crate::resource::Exits>();
+                SpriteRender::new(exits.sheet.clone(), exits.stairs_up)
+            };
+            let (pos, z) = self.start();
+            world
+                .create_entity()
+                .with(crate::component::StairsUp)
+                .with(Hidden)
+                .with(crate::component::Position {
+                    pos,
+                    map: self.walls.unwrap(),
+                    z,
+                })
+                .with(stairs)
+                .with(sprite)
+                .build();
+        }
+    }
+
+    /// Puts the level away as it is when the player leaves it, to be restored when they come
+    /// back. Everything on it is hidden and stays where it is, projectiles in flight are dropped.
+    fn stash(&self, world: &mut World) {
+        let (player, walls, floors) = (
+            self.player.unwrap(),
+            self.walls.unwrap(),
+            self.floors.unwrap(),
+        );
+        world.exec(
+            |(entities, positions, projectiles, mut intents, mut hiddens): (
+                Entities<'_>,
+                ReadStorage<'_, crate::component::Position>,
+                ReadStorage<'_, crate::component::Projectile>,
+                WriteStorage<'_, crate::component::MovementIntent>,
+                WriteStorage<'_, Hidden>,
+            )| {
+                hiddens.insert(floors, Hidden);
+                hiddens.insert(walls, Hidden);
+
+                for (entity, position) in (&entities, &positions).join() {
+                    if entity == player || position.map != walls {
+                        continue;
+                    }
+                    if projectiles.get(entity).is_some() {
+                        entities.delete(entity);
+                    } else {
+                        intents.remove(entity);
+                        hiddens.insert(entity, Hidden);
+                    }
                 }
             },
         );
+
+        let surface = world.remove::<crate::resource::Surface>();
+        world
+            .write_resource::<crate::resource::VisitedLevels>()
+            .insert(
+                self.level,
+                crate::resource::StoredLevel {
+                    walls,
+                    floors,
+                    surface,
+                },
+            );
+    }
+
+    /// Brings back a level put away by `stash`, the visibility system shows what is in sight.
+    fn restore(&mut self, world: &mut World, stored: crate::resource::StoredLevel) {
+        self.walls = Some(stored.walls);
+        self.floors = Some(stored.floors);
+        world.exec(|mut hiddens: WriteStorage<'_, Hidden>| {
+            hiddens.remove(stored.walls);
+            hiddens.remove(stored.floors);
+        });
+        match stored.surface {
+            Some(surface) => world.insert(surface),
+            None => {
+                world.remove::<crate::resource::Surface>();
+            }
+        }
+
+        let music = world
+            .read_resource::<crate::resource::Levels>()
+            .get(self.level)
+            .and_then(|def| def.music.clone());
+        if let Some(music) = &music {
+            crate::audio::play_music(world, music);
+        }
+        world.write_resource::<crate::state::Sanity>().notice = None;
+    }
+
+    /// Where the entity marked with `T` is on this level, like the exit or the stairs up.
+    fn find<T: Component>(&self, world: &mut World) -> Option<(Point, u32)> {
+        let walls = self.walls?;
+        world.exec(
+            |(marks, positions): (
+                ReadStorage<'_, T>,
+                ReadStorage<'_, crate::component::Position>,
+            )| {
+                (&marks, &positions)
+                    .join()
+                    .find(|(_, p)| p.map == walls)
+                    .map(|(_, p)| (p.pos, p.z))
+            },
+        )
+    }
+
+    /// Puts the player on this level at `at`.
+    fn place_player(&self, world: &mut World, (at, z): (Point, u32)) {
+        world.exec(
+            |(mut positions, maps, mut transforms): (
+                WriteStorage<'_, crate::component::Position>,
+                WriteStorage<'_, TileMap<RoomTile>>,
+                WriteStorage<'_, Transform>,
+            )| {
+                let mut pos = positions.get_mut(self.player.unwrap()).unwrap();
+                pos.map = self.walls.unwrap();
+                pos.pos = at;
+                pos.z = z;
+
+                let p = maps.get(self.walls.unwrap()).unwrap();
+                let mut t = Transform::from(p.to_world(&pos.xyz(), None));
+                t.move_up(8.);
+                t.move_backward(1.);
+                transforms.insert(self.player.unwrap(), t);
+            },
+        );
     }
 }
 
@@ -155,35 +597,32 @@ impl<'a, 'b> State<crate::gamedata::CustomGameData<'a, 'b>, StateEvent> for Room
     fn on_start(&mut self, data: StateData<'_, CustomGameData<'a, 'b>>) {
         let StateData { mut world, .. } = data;
 
-        self.init_map(world);
+        let stored = world
+            .write_resource::<crate::resource::VisitedLevels>()
+            .take(self.level);
+        let revisit = stored.is_some();
+        match stored {
+            Some(stored) => self.restore(world, stored),
+            None => self.init_map(world),
+        }
 
-        let start = Point::new(self.width / 2, self.height / 2);
+        // back down on a level played before the player comes out of its stairs up
+        let start = if revisit {
+            self.find::<crate::component::StairsUp>(world)
+        } else {
+            None
+        }
+        .unwrap_or_else(|| self.start());
 
         if self.player.is_none() {
             self.init_player(world, start);
         } else {
             println!("Moving Player to start position");
-            world.exec(
-                |(mut positions, maps, mut transforms): (
-                    WriteStorage<'_, crate::component::Position>,
-                    WriteStorage<'_, TileMap<RoomTile>>,
-                    WriteStorage<'_, Transform>,
-                )| {
-                    let mut pos = positions.get_mut(self.player.unwrap()).unwrap();
-                    pos.map = self.walls.unwrap();
-                    pos.pos = start;
-
-                    let p = maps.get(self.walls.unwrap()).unwrap();
-                    let mut t = Transform::from(
-                        p.to_world(&Point3::new(pos.pos.x as u32, pos.pos.y as u32, 0), None),
-                    );
-                    t.move_up(8.);
-                    t.move_backward(1.);
-                    transforms.insert(self.player.unwrap(), t);
-                },
-            );
+            self.place_player(world, start);
+        }
+        if !revisit {
+            self.begin_level(world);
         }
-        self.gen_map_exec(world);
 
         if self.camera.is_none() {
             self.init_camera(world);
@@ -192,6 +631,7 @@ impl<'a, 'b> State<crate::gamedata::CustomGameData<'a, 'b>, StateEvent> for Room
         if self.hud.is_none() {
             world.exec(|mut creator: UiCreator<'_>| {
                 self.hud = Some(creator.create("ui/hud.ron", ()));
+                creator.create("ui/seed.ron", ());
             });
         }
 
@@ -204,6 +644,9 @@ impl<'a, 'b> State<crate::gamedata::CustomGameData<'a, 'b>, StateEvent> for Room
 
     fn on_resume(&mut self, data: StateData<'_, CustomGameData<'a, 'b>>) {
         let StateData { world, .. } = data;
+        let stored = world
+            .write_resource::<crate::resource::VisitedLevels>()
+            .take(self.level);
 
         let mut restart = false;
         {
@@ -215,20 +658,31 @@ impl<'a, 'b> State<crate::gamedata::CustomGameData<'a, 'b>, StateEvent> for Room
         }
 
         if restart {
+            // the levels the player left are kept as they are
+            let walls = self.walls.unwrap();
             world.exec(
                 |(entities, positions): (
                     Entities<'_>,
                     ReadStorage<'_, crate::component::Position>,
                 )| {
-                    for (entity, _) in (&entities, &positions).join() {
-                        entities.delete(entity); // also deletes camera child
+                    for (entity, position) in (&entities, &positions).join() {
+                        if position.map == walls {
+                            entities.delete(entity); // also deletes camera child
+                        }
                     }
                 },
             );
             world.maintain();
-            self.init_player(world, Point::new(self.width / 2, self.height / 2));
+            self.init_player(world, self.start());
             self.init_camera(world);
-            self.gen_map_exec(world);
+            self.begin_level(world);
+        } else if let Some(stored) = stored {
+            // back up the stairs, the player comes out of the exit they took down
+            self.restore(world, stored);
+            let at = self
+                .find::<crate::component::Exit>(world)
+                .unwrap_or_else(|| self.start());
+            self.place_player(world, at);
         }
     }
 
@@ -238,7 +692,14 @@ impl<'a, 'b> State<crate::gamedata::CustomGameData<'a, 'b>, StateEvent> for Room
     ) -> Trans<CustomGameData<'a, 'b>, StateEvent> {
         let StateData { mut world, .. } = data;
 
+        // the game waits while the level is still being generated
+        if self.loading.is_some() && !self.poll_level(world) {
+            data.data.update(&world, false);
+            return Trans::None;
+        }
+
         let mut descend = false;
+        let mut ascend = false;
 
         {
             let sanity_res = world.read_resource::<crate::state::Sanity>();
@@ -249,39 +710,25 @@ impl<'a, 'b> State<crate::gamedata::CustomGameData<'a, 'b>, StateEvent> for Room
 
             if sanity_res.level.len() > self.level {
                 descend = true;
+            } else if sanity_res.level.len() < self.level {
+                ascend = true;
             }
         }
 
+        if ascend {
+            println!("Ascending");
+            self.stash(world);
+            return Trans::Pop;
+        }
+
         if descend {
             println!("Descending");
-            world.exec(
-                |(entities, positions, mut hiddens): (
-                    Entities<'_>,
-                    ReadStorage<'_, crate::component::Position>,
-                    WriteStorage<'_, Hidden>,
-                )| {
-                    hiddens.insert(self.floors.unwrap(), Hidden);
-                    hiddens.insert(self.walls.unwrap(), Hidden);
-
-                    for (entity, _) in (&entities, &positions).join() {
-                        if entity != self.player.unwrap() {
-                            entities.delete(entity);
-                        }
-                    }
-                },
-            );
+            self.stash(world);
             return Trans::Push(Box::new(RoomState {
-                level: self.level + 1,
-                width: LEVEL_SIZES[self.level].0,
-                height: LEVEL_SIZES[self.level].1,
                 player: self.player,
                 camera: self.camera,
-                player_anim: self.player_anim.clone(),
-                map_spritesheet: self.map_spritesheet.clone(),
-                pairs: self.pairs.clone(),
-                walls: None,
-                floors: None,
                 hud: self.hud,
+                ..RoomState::for_level(world, self.level + 1, self.player_anim.clone())
             }));
         }
 
@@ -1,14 +1,16 @@
+use crate::resource::Purpose;
 use amethyst::{
     core::timing::Time,
     derive::SystemDesc,
     ecs::{
-        prelude::{Read, System, SystemData, WriteStorage},
+        prelude::{Read, System, SystemData, Write, WriteStorage},
         Entities, Join, ReadStorage,
     },
-    tiles::{Map, TileMap},
+    tiles::TileMap,
 };
-use bracket_pathfinding::prelude::{Point, *};
+use bracket_pathfinding::prelude::*;
 use core::time::Duration;
+use rand::seq::SliceRandom;
 use sanity_lib::tile::RoomTile;
 
 #[derive(Default, SystemDesc)]
@@ -27,6 +29,9 @@ impl<'a> System<'a> for AISystem {
         ReadStorage<'a, crate::component::Health>,
         Read<'a, Time>,
         Read<'a, crate::state::Sanity>,
+        Write<'a, crate::resource::GameRng>,
+        Read<'a, crate::resource::Settings>,
+        Read<'a, crate::resource::DijkstraMaps>,
     );
 
     fn run(
@@ -41,6 +46,9 @@ impl<'a> System<'a> for AISystem {
             healths,
             time,
             sanity_res,
+            mut rng,
+            settings,
+            maps,
         ): Self::SystemData,
     ) {
         for (entity, enemy) in (&entities, &enemies).join() {
@@ -58,42 +66,47 @@ impl<'a> System<'a> for AISystem {
             self.last_move = time.absolute_time();
 
             if let Some(map_ent) = sanity_res.level.last().unwrap_or(&None) {
-                if let Some(tilemap) = tilemaps.get_mut(*map_ent) {
-                    let dim = *tilemap.dimensions();
-                    let (width, height) = (dim.x, dim.y);
-
-                    let my_map = sanity_lib::map::SanityMap(tilemap);
+                if let (Some(tilemap), Some(chase), Some(flee)) = (
+                    tilemaps.get_mut(*map_ent),
+                    maps.get(*map_ent, Purpose::Player),
+                    maps.get(*map_ent, Purpose::Flee),
+                ) {
+                    let my_map = sanity_lib::map::SanityMap(tilemap, settings.moves);
 
                     for (player_entity, player, player_pos) in
                         (&entities, &players, &positions).join()
                     {
-                        let player_idx =
-                            my_map.point2d_to_index(Point::new(player_pos.pos.x, player_pos.pos.y));
-
-                        let dijkstra =
-                            DijkstraMap::new(width, height, &[player_idx], &my_map, 1000.);
+                        let player_idx = my_map.index(player_pos.pos, player_pos.z);
 
+                        // enemies on the levels the player left stay where they are
                         for (entity, enemy, position, health) in
-                            (&entities, &enemies, &positions, &healths).join()
+                            (&entities, &enemies, &positions, &healths)
+                                .join()
+                                .filter(|(_, _, p, _)| p.map == *map_ent)
                         {
                             if healths.get(player_entity).is_some()
                                 && healths.get(player_entity).unwrap().current > 0
                             {
-                                let e_pos = my_map.point2d_to_index(position.pos);
+                                // badly hurt enemies run away instead
+                                let fleeing = health.current * 4 <= health.max as i32;
+                                let dijkstra = if fleeing { flee } else { chase };
+
+                                let e_pos = my_map.index(position.pos, position.z);
                                 if let Some(target) =
-                                    DijkstraMap::find_lowest_exit(&dijkstra, e_pos, &my_map)
+                                    DijkstraMap::find_lowest_exit(dijkstra, e_pos, &my_map)
                                 {
-                                    let target_pos = my_map.index_to_point2d(target);
+                                    let (target_pos, _) = my_map.position(target);
                                     let dist = my_map.get_pathing_distance(player_idx, e_pos);
                                     let coord_pt = target_pos - position.pos;
                                     let player_coord_pt = player_pos.pos - position.pos;
 
                                     //println!("{:?} {:?}", position.pos, dist);
-                                    if dist > 1. {
+                                    // right above or below the player it has to get round first
+                                    if fleeing || dist > 1. || position.z != player_pos.z {
                                         intents.insert(
                                             entity,
                                             crate::component::MovementIntent {
-                                                dir: direction::CardinalDirection::from_unit_coord(
+                                                dir: direction::Direction::from_unit_coord(
                                                     direction::Coord::new(coord_pt.x, coord_pt.y),
                                                 ),
                                                 step: 5,
@@ -105,7 +118,7 @@ impl<'a> System<'a> for AISystem {
                                         intents.insert(
                                             entity,
                                             crate::component::MovementIntent {
-                                                dir: direction::CardinalDirection::from_unit_coord(
+                                                dir: direction::Direction::from_unit_coord(
                                                     direction::Coord::new(
                                                         player_coord_pt.x,
                                                         player_coord_pt.y,
@@ -117,10 +130,14 @@ impl<'a> System<'a> for AISystem {
                                     }
                                 }
                             } else {
+                                // wander any way the run allows
+                                let ways: Vec<direction::Direction> = direction::Direction::all()
+                                    .filter(|dir| settings.moves.allows(*dir))
+                                    .collect();
                                 intents.insert(
                                     entity,
                                     crate::component::MovementIntent {
-                                        dir: rand::random(),
+                                        dir: *ways.choose(&mut rng.0).unwrap(),
                                         step: 5,
                                     },
                                 );
@@ -0,0 +1,164 @@
+use crate::resource::ChunkJob;
+use amethyst::{
+    core::{math::Point3, Transform},
+    derive::SystemDesc,
+    ecs::prelude::*,
+    tiles::{Map, MapStorage, TileMap},
+};
+use bracket_pathfinding::prelude::Point;
+use sanity_lib::{
+    chunks::WINDOW,
+    regions::Regions,
+    tile::{FloorTile, RoomTile},
+};
+use std::collections::BTreeSet;
+
+/// Keeps the player in the middle chunk of a surface level. The ring of chunks around the
+/// window is generated on a worker ahead of time. When the player steps out of the middle chunk
+/// the window follows them: everything on the map is moved back by as many cells as the window
+/// moved, and whatever falls outside of it is removed. Exits and the stairs back up stay where
+/// they are and come back when the player gets near.
+#[derive(Default, SystemDesc)]
+pub struct ChunkSystem {}
+
+impl<'a> System<'a> for ChunkSystem {
+    type SystemData = (
+        Entities<'a>,
+        Option<Write<'a, crate::resource::Surface>>,
+        Read<'a, crate::state::Sanity>,
+        WriteStorage<'a, TileMap<RoomTile>>,
+        WriteStorage<'a, TileMap<FloorTile>>,
+        WriteStorage<'a, crate::component::Position>,
+        WriteStorage<'a, Transform>,
+        ReadStorage<'a, crate::component::Player>,
+        ReadStorage<'a, crate::component::Exit>,
+        ReadStorage<'a, crate::component::StairsUp>,
+        WriteStorage<'a, crate::component::Regions>,
+    );
+
+    fn run(
+        &mut self,
+        (
+            entities,
+            surface,
+            sanity_res,
+            mut wall_maps,
+            mut floor_maps,
+            mut positions,
+            mut transforms,
+            players,
+            exits,
+            stairs,
+            mut regions,
+        ): Self::SystemData,
+    ) {
+        let mut surface = match surface {
+            Some(surface) => surface,
+            None => return,
+        };
+        let (map_ent, floor_ent) = match (sanity_res.level.last(), sanity_res.floor.last()) {
+            (Some(Some(walls)), Some(Some(floor))) => (*walls, *floor),
+            _ => return,
+        };
+        // the worker fills in the chunks that are still missing here
+        if let Some(ahead) = surface.ahead.as_mut().and_then(ChunkJob::try_take) {
+            surface.chunks.merge(ahead);
+            surface.ahead = None;
+        }
+        let centre = (surface.origin.0 + WINDOW / 2, surface.origin.1 + WINDOW / 2);
+        if surface.ahead.is_none() && !surface.chunks.has_ring(centre) {
+            let (chunks, budget) = (surface.chunks.clone(), surface.budget);
+            surface.ahead = Some(ChunkJob::spawn(chunks, centre, budget));
+        }
+
+        let player = match (&positions, &players).join().next() {
+            Some((position, _)) => position.pos,
+            None => return,
+        };
+
+        let size = surface.chunks.size as i32;
+        let shift = (
+            player.x.div_euclid(size) - WINDOW / 2,
+            player.y.div_euclid(size) - WINDOW / 2,
+        );
+        if shift == (0, 0) {
+            return;
+        }
+
+        let (walls, floor) = match (wall_maps.get_mut(map_ent), floor_maps.get_mut(floor_ent)) {
+            (Some(walls), Some(floor)) => (walls, floor),
+            _ => return,
+        };
+        let side = WINDOW * size;
+        let (dx, dy) = (shift.0 * size, shift.1 * size);
+        let inside = |x: i32, y: i32| x >= 0 && y >= 0 && x < side && y < side;
+
+        // what the player has already seen stays seen
+        let visited: Vec<(Point3<u32>, bool)> = (0..side)
+            .flat_map(|y| (0..side).map(move |x| (x, y)))
+            .filter(|(x, y)| inside(x + dx, y + dy))
+            .map(|(x, y)| {
+                let from = Point3::new((x + dx) as u32, (y + dy) as u32, 0);
+                let seen = floor.get(&from).map_or(false, |t| t.visited);
+                (Point3::new(x as u32, y as u32, 0), seen)
+            })
+            .collect();
+
+        surface.origin = (surface.origin.0 + shift.0, surface.origin.1 + shift.1);
+        // only what the worker hasn't got to yet is generated here
+        let (origin, budget) = (surface.origin, surface.budget);
+        for outcome in surface.chunks.show(origin, walls, floor, budget) {
+            if let Err(e) = outcome {
+                log::info!("Leaving a chunk open: {}", e);
+            }
+        }
+        let centre = (origin.0 + WINDOW / 2, origin.1 + WINDOW / 2);
+        let forgotten = surface.chunks.unload(centre);
+        log::debug!(
+            "Moved the surface to chunk {:?}, forgot {} chunks",
+            centre,
+            forgotten
+        );
+
+        for (at, seen) in visited {
+            if let Some(tile) = floor.get_mut(&at) {
+                tile.visited = seen;
+            }
+        }
+
+        let (tile_width, tile_height) = (
+            walls.tile_dimensions().x as f32,
+            walls.tile_dimensions().y as f32,
+        );
+        for (entity, position) in (&entities, &mut positions).join() {
+            if position.map != map_ent {
+                continue;
+            }
+
+            position.pos = Point::new(position.pos.x - dx, position.pos.y - dy);
+            let stays = exits.get(entity).is_some() || stairs.get(entity).is_some();
+            if !inside(position.pos.x, position.pos.y) && !stays {
+                entities.delete(entity);
+                continue;
+            }
+
+            if let Some(transform) = transforms.get_mut(entity) {
+                transform.prepend_translation_x(-dx as f32 * tile_width);
+                // world coords are inverted from grid coords on y
+                transform.prepend_translation_y(dy as f32 * tile_height);
+            }
+        }
+
+        // the corridors around the start are long gone
+        regions
+            .insert(
+                map_ent,
+                crate::component::Regions {
+                    regions: Regions::new(walls),
+                    guarded: BTreeSet::new(),
+                    storey: 0,
+                },
+            )
+            .expect("Walls Exist");
+    }
+}
@@ -0,0 +1,156 @@
+use crate::resource::{Built, DijkstraMaps, Purpose};
+use amethyst::{
+    core::math::Point3,
+    derive::SystemDesc,
+    ecs::{
+        prelude::{System, SystemData, WriteStorage},
+        Join, Read, ReadStorage, Write,
+    },
+    tiles::{Map, MapStorage, TileMap},
+};
+use bracket_pathfinding::prelude::*;
+use sanity_lib::{map::SanityMap, tile::RoomTile};
+use std::collections::VecDeque;
+
+/// How far the maps look, cells further away are left at `f32::MAX`.
+const MAX_DEPTH: f32 = 1000.;
+
+/// Builds the shared `DijkstraMaps` of the current level, only when the player has moved, the
+/// level changed or its walls were invalidated.
+#[derive(Default, SystemDesc)]
+pub struct DijkstraSystem {}
+
+impl<'a> System<'a> for DijkstraSystem {
+    type SystemData = (
+        WriteStorage<'a, TileMap<RoomTile>>,
+        ReadStorage<'a, crate::component::Player>,
+        ReadStorage<'a, crate::component::Position>,
+        ReadStorage<'a, crate::component::Exit>,
+        Read<'a, crate::state::Sanity>,
+        Read<'a, crate::resource::Settings>,
+        Write<'a, DijkstraMaps>,
+    );
+
+    fn run(
+        &mut self,
+        (mut tilemaps, players, positions, exits, sanity_res, settings, mut maps): Self::SystemData,
+    ) {
+        let map_ent = match sanity_res.level.last() {
+            Some(Some(map_ent)) => *map_ent,
+            _ => return,
+        };
+        let player = match (&positions, &players).join().next() {
+            Some((position, _)) if position.map == map_ent => (position.pos, position.z),
+            _ => return,
+        };
+        let built = Built {
+            map: map_ent,
+            player,
+            version: maps.version(),
+        };
+        if maps.built == Some(built) {
+            return;
+        }
+        let tilemap = match tilemaps.get_mut(map_ent) {
+            Some(tilemap) => tilemap,
+            None => return,
+        };
+
+        let dim = *tilemap.dimensions();
+        let (width, height) = (dim.x, dim.y * dim.z);
+        let walkable = |walls: &TileMap<RoomTile>, x: i32, y: i32, z: u32| {
+            x >= 0
+                && y >= 0
+                && x < dim.x as i32
+                && y < dim.y as i32
+                && walls
+                    .get(&Point3::new(x as u32, y as u32, z))
+                    .map_or(false, |t| t.walkable)
+        };
+
+        // explored tiles with an unexplored one next to them
+        let mut frontier = vec![];
+        for z in 0..dim.z {
+            for y in 0..dim.y as i32 {
+                for x in 0..dim.x as i32 {
+                    let visited = |x: i32, y: i32| {
+                        tilemap
+                            .get(&Point3::new(x as u32, y as u32, z))
+                            .map_or(false, |t| t.visited)
+                    };
+                    if walkable(tilemap, x, y, z)
+                        && visited(x, y)
+                        && [(-1, 0), (1, 0), (0, -1), (0, 1)].iter().any(|(dx, dy)| {
+                            walkable(tilemap, x + dx, y + dy, z) && !visited(x + dx, y + dy)
+                        })
+                    {
+                        frontier.push((Point::new(x, y), z));
+                    }
+                }
+            }
+        }
+
+        let exit = (&exits, &positions)
+            .join()
+            .find(|(_, p)| {
+                p.map == map_ent
+                    && p.pos.x >= 0
+                    && p.pos.y >= 0
+                    && p.pos.x < dim.x as i32
+                    && p.pos.y < dim.y as i32
+            })
+            .map(|(_, p)| (p.pos, p.z));
+
+        let my_map = SanityMap(tilemap, settings.moves);
+        let index = |(pt, z): (Point, u32)| my_map.index(pt, z);
+
+        let to_player = DijkstraMap::new(width, height, &[index(player)], &my_map, MAX_DEPTH);
+
+        // the player map turned upside down and spread out again, so running downhill heads for
+        // the places far from the player rather than just the nearest corner
+        let seeds = to_player
+            .map
+            .iter()
+            .map(|d| if *d < MAX_DEPTH { -1.2 * d } else { f32::MAX })
+            .collect();
+        let mut flee = DijkstraMap::new_empty(width, height, MAX_DEPTH);
+        flee.map = relax(seeds, &my_map);
+
+        let frontier: Vec<usize> = frontier.into_iter().map(index).collect();
+        maps.maps.clear();
+        if !frontier.is_empty() {
+            maps.maps.insert(
+                Purpose::Frontier,
+                DijkstraMap::new(width, height, &frontier, &my_map, MAX_DEPTH),
+            );
+        }
+        if let Some(exit) = exit {
+            maps.maps.insert(
+                Purpose::Exit,
+                DijkstraMap::new(width, height, &[index(exit)], &my_map, MAX_DEPTH),
+            );
+        }
+        maps.maps.insert(Purpose::Player, to_player);
+        maps.maps.insert(Purpose::Flee, flee);
+        maps.built = Some(built);
+    }
+}
+
+/// Lowers every cell to its cheapest neighbour plus the cost of the step from it, until nothing
+/// changes.
+fn relax(mut values: Vec<f32>, map: &SanityMap) -> Vec<f32> {
+    let mut open: VecDeque<usize> = (0..values.len())
+        .filter(|idx| values[*idx] < f32::MAX)
+        .collect();
+
+    while let Some(idx) = open.pop_front() {
+        for (next, cost) in map.get_available_exits(idx) {
+            if values[idx] + cost < values[next] {
+                values[next] = values[idx] + cost;
+                open.push_back(next);
+            }
+        }
+    }
+
+    values
+}
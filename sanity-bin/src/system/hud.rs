@@ -1,23 +1,38 @@
 use amethyst::{
     derive::SystemDesc,
     ecs::{prelude::*, Entity},
-    ui::UiTransform,
+    ui::{UiText, UiTransform},
 };
 
 #[derive(Default, SystemDesc)]
 pub struct HUDSystem {
     health_bar: Option<Entity>,
+    seed_display: Option<Entity>,
 }
 
 impl<'a> System<'a> for HUDSystem {
     type SystemData = (
         Entities<'a>,
         WriteStorage<'a, UiTransform>,
+        WriteStorage<'a, UiText>,
         ReadStorage<'a, crate::component::Player>,
         ReadStorage<'a, crate::component::Health>,
+        Read<'a, crate::resource::Seed>,
+        Read<'a, crate::state::Sanity>,
     );
 
-    fn run(&mut self, (entities, mut ui_transform, players, healths): Self::SystemData) {
+    fn run(
+        &mut self,
+        (
+            entities,
+            mut ui_transform,
+            mut ui_text,
+            players,
+            healths,
+            seed,
+            sanity_res,
+        ): Self::SystemData,
+    ) {
         if self.health_bar.is_none() {
             self.health_bar = (&entities, &ui_transform)
                 .join()
@@ -37,5 +52,22 @@ impl<'a> System<'a> for HUDSystem {
                 }
             }
         }
+
+        if self.seed_display.is_none() {
+            self.seed_display = (&entities, &ui_transform)
+                .join()
+                .find(|x| x.1.id == "seed_text")
+                .map(|x| x.0);
+        }
+
+        if let Some(seed_display) = self.seed_display.and_then(|e| ui_text.get_mut(e)) {
+            let mut text = format!("Seed {} Level {}", seed.0, sanity_res.level.len());
+            if let Some(notice) = &sanity_res.notice {
+                text = format!("{}\n{}", text, notice);
+            }
+            if seed_display.text != text {
+                seed_display.text = text;
+            }
+        }
     }
 }
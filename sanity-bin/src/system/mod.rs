@@ -1,12 +1,15 @@
 pub mod ai;
+pub mod chunk;
 pub mod collision;
 pub mod death;
+pub mod dijkstra;
 pub mod fps;
 pub mod hud;
 pub mod idle;
 pub mod lose;
 pub mod movement;
 pub mod player;
+pub mod secret;
 pub mod shooting;
 pub mod spawn;
 pub mod visibility;
@@ -9,7 +9,10 @@ use amethyst::{
     tiles::{Map, MapStorage, TileMap},
 };
 use bracket_pathfinding::prelude::Point;
-use sanity_lib::tile::RoomTile;
+use sanity_lib::{
+    map::{can_fire, can_step},
+    tile::RoomTile,
+};
 
 #[derive(Default, SystemDesc)]
 pub struct MovementSystem {}
@@ -31,6 +34,7 @@ impl<'a> System<'a> for MovementSystem {
         WriteStorage<'a, AnimationControlSet<usize, SpriteRender>>,
         ReadStorage<'a, crate::component::Item>,
         ReadStorage<'a, crate::component::Exit>,
+        ReadStorage<'a, crate::component::StairsUp>,
         Write<'a, crate::state::Sanity>,
     );
 
@@ -52,6 +56,7 @@ impl<'a> System<'a> for MovementSystem {
             mut control_sets,
             items,
             exits,
+            stairs,
             mut sanity_res,
         ): Self::SystemData,
     ) {
@@ -61,6 +66,7 @@ impl<'a> System<'a> for MovementSystem {
                     (&entities, &positions, &enemies, &healths).join().collect();
 
                 let mut intents_to_cancel: Vec<Entity> = vec![];
+                let mut arrived: Vec<Entity> = vec![];
 
                 for (player_entity, player_pos, _) in (&entities, &positions, &players).join() {
                     // Player wants to move.
@@ -70,10 +76,14 @@ impl<'a> System<'a> for MovementSystem {
                         let target = player_pos.pos + p;
 
                         // Enemy is in place that player want's to move.  Melee attack.
-                        if let Some(enemy) = enemy_positions
-                            .iter()
-                            .find(|x| x.1.pos == target && x.1.map == *map_ent)
-                        {
+                        // Not round the corner of a wall though.
+                        let reachable = can_step(tilemap, player_pos.xyz(), p);
+                        if let Some(enemy) = enemy_positions.iter().find(|x| {
+                            reachable
+                                && x.1.pos == target
+                                && x.1.map == *map_ent
+                                && x.1.z == player_pos.z
+                        }) {
                             // there's an enemy on this position
                             intents.remove(player_entity);
                             intents.remove(enemy.0);
@@ -95,7 +105,7 @@ impl<'a> System<'a> for MovementSystem {
                         let p = Point::new(c.x, c.y);
                         let target = position.pos + p;
 
-                        if target == player_pos.pos {
+                        if target == player_pos.pos && position.z == player_pos.z {
                             // enemy attacks player
                             if let Some(animation_set) = animation_sets.get(entity) {
                                 let control_set =
@@ -119,10 +129,9 @@ impl<'a> System<'a> for MovementSystem {
                                     with: Some(player_entity),
                                 },
                             );
-                        } else if let Some(enemy) = enemy_positions
-                            .iter()
-                            .find(|x| x.1.pos == target && x.1.map == *map_ent)
-                        {
+                        } else if let Some(enemy) = enemy_positions.iter().find(|x| {
+                            x.1.pos == target && x.1.map == *map_ent && x.1.z == position.z
+                        }) {
                             // there's an enemy on this position
                             intents_to_cancel.push(entity);
                         }
@@ -140,10 +149,18 @@ impl<'a> System<'a> for MovementSystem {
                     let p = Point::new(c.x, c.y);
                     let target = position.pos + p;
 
-                    if let Some(tile) =
-                        tilemap.get(&Point3::new(target.x as u32, target.y as u32, 0))
+                    // shots fly over whatever can be seen through, the rest walk
+                    let open = if projectiles.get(entity).is_some() {
+                        can_fire(tilemap, position.xyz(), p)
+                    } else {
+                        can_step(tilemap, position.xyz(), p)
+                    };
+
+                    if tilemap
+                        .get(&Point3::new(target.x as u32, target.y as u32, position.z))
+                        .is_some()
                     {
-                        if tile.walkable {
+                        if open {
                             if let Some(animation_set) = animation_sets.get(entity) {
                                 let control_set =
                                     get_animation_set(&mut control_sets, entity).unwrap();
@@ -172,8 +189,23 @@ impl<'a> System<'a> for MovementSystem {
 
                             if intent.step == 0 {
                                 position.pos = target;
+
+                                // ladders, lifts and holes take whatever walks on to them to
+                                // another storey, projectiles fly over them
+                                let at = position.xyz();
+                                if let (None, Some(z)) = (
+                                    projectiles.get(entity),
+                                    sanity_lib::map::landing(tilemap, at),
+                                ) {
+                                    let to = Point3::new(at.x, at.y, z);
+                                    transform.prepend_translation(
+                                        tilemap.to_world(&to, None) - tilemap.to_world(&at, None),
+                                    );
+                                    position.z = z;
+                                }
                                 if players.get(entity).is_some() {
-                                    println!("Moved to {:?}", target);
+                                    log::debug!("Moved to {:?}", target);
+                                    arrived.push(entity);
                                 }
                                 if projectiles.get(entity).is_some() {
                                     intent.step = 5;
@@ -204,7 +236,7 @@ impl<'a> System<'a> for MovementSystem {
                 for (p_ent, p_pos, _) in (&entities, &positions, &projectiles).join() {
                     for (c_ent, c_pos, _, _) in enemy_positions.iter() {
                         if *p_pos == **c_pos {
-                            println!("Colission");
+                            log::debug!("Projectile hit at {:?}", p_pos.pos);
                             hiddens.insert(p_ent, Hidden);
                             // inserts a collision on the entity occupying space projectile is in
                             collisions.insert(
@@ -223,7 +255,9 @@ impl<'a> System<'a> for MovementSystem {
                 }
 
                 // collision with items
-                for (player, p_position) in (&mut players, &positions).join() {
+                for (player_entity, player, p_position) in
+                    (&entities, &mut players, &positions).join()
+                {
                     for (ent, item, i_position) in (&entities, &items, &positions).join() {
                         if p_position == i_position {
                             println!("Collected item {:?}", item.item);
@@ -232,6 +266,11 @@ impl<'a> System<'a> for MovementSystem {
                         }
                     }
 
+                    // stairs are taken by stepping on to them, not by arriving on them
+                    if !arrived.contains(&player_entity) {
+                        continue;
+                    }
+
                     for (ent, exit, e_position) in (&entities, &exits, &positions).join() {
                         if p_position == e_position {
                             println!("Exit Found!");
@@ -239,6 +278,14 @@ impl<'a> System<'a> for MovementSystem {
                             sanity_res.floor.push(None);
                         }
                     }
+
+                    for (_, s_position) in (&stairs, &positions).join() {
+                        if p_position == s_position {
+                            println!("Going back up");
+                            sanity_res.level.pop();
+                            sanity_res.floor.pop();
+                        }
+                    }
                 }
             }
         }
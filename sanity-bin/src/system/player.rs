@@ -22,9 +22,10 @@ impl<'a> System<'a> for PlayerSystem {
         WriteStorage<'a, crate::component::MovementIntent>,
         Read<'a, Time>,
         Entities<'a>,
+        Read<'a, crate::resource::Settings>,
     );
 
-    fn run(&mut self, (input, players, mut intents, time, entities): Self::SystemData) {
+    fn run(&mut self, (input, players, mut intents, time, entities, settings): Self::SystemData) {
         for (entity, _) in (&entities, &players).join() {
             if let Some(intent) = intents.get(entity) {
                 if intent.step == 0 {
@@ -38,13 +39,18 @@ impl<'a> System<'a> for PlayerSystem {
             // check if player is attempting to move again
             if time.absolute_time() - self.last_move > Duration::from_millis(150) {
                 for dir in &[
-                    ("up", direction::CardinalDirection::North),
-                    ("down", direction::CardinalDirection::South),
-                    ("left", direction::CardinalDirection::West),
-                    ("right", direction::CardinalDirection::East),
+                    ("up", direction::Direction::North),
+                    ("down", direction::Direction::South),
+                    ("left", direction::Direction::West),
+                    ("right", direction::Direction::East),
+                    ("up_left", direction::Direction::NorthWest),
+                    ("up_right", direction::Direction::NorthEast),
+                    ("down_left", direction::Direction::SouthWest),
+                    ("down_right", direction::Direction::SouthEast),
                 ] {
-                    if input.action_is_down(dir.0).unwrap_or(false) {
-                        println!("{}", dir.0);
+                    if settings.moves.allows(dir.1) && input.action_is_down(dir.0).unwrap_or(false)
+                    {
+                        log::debug!("Moving {}", dir.0);
                         self.last_move = time.absolute_time();
                         intents
                             .insert(
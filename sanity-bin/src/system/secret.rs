@@ -0,0 +1,64 @@
+use crate::component::item::ItemType;
+use amethyst::{
+    core::{math::Point3, timing::Time},
+    derive::SystemDesc,
+    ecs::{
+        prelude::{System, SystemData, WriteStorage},
+        Join, Read, ReadStorage, Write,
+    },
+    input::{InputHandler, StringBindings},
+    tiles::{MapStorage, TileMap},
+};
+use sanity_lib::tile::RoomTile;
+
+/// Opens the secret doors the player finds. Searching finds the ones right next to them, and
+/// with a flashlight they are spotted from two tiles away without searching.
+#[derive(Default, SystemDesc)]
+pub struct SecretSystem {}
+
+impl<'a> System<'a> for SecretSystem {
+    type SystemData = (
+        Read<'a, InputHandler<StringBindings>>,
+        ReadStorage<'a, crate::component::Player>,
+        ReadStorage<'a, crate::component::Position>,
+        WriteStorage<'a, TileMap<RoomTile>>,
+        Read<'a, Time>,
+        Read<'a, crate::state::Sanity>,
+        Write<'a, crate::resource::DijkstraMaps>,
+    );
+
+    fn run(
+        &mut self,
+        (input, players, positions, mut tilemaps, time, sanity_res, mut maps): Self::SystemData,
+    ) {
+        if let Some(map_ent) = sanity_res.level.last().unwrap_or(&None) {
+            if let Some(walls) = tilemaps.get_mut(*map_ent) {
+                for (player, position) in (&players, &positions).join() {
+                    let reach = if player.inventory.contains(&ItemType::Flashlight) {
+                        2
+                    } else if input.action_is_down("search").unwrap_or(false) {
+                        1
+                    } else {
+                        continue;
+                    };
+
+                    for dy in -reach..=reach {
+                        for dx in -reach..=reach {
+                            let (x, y) = (position.pos.x + dx, position.pos.y + dy);
+                            if x < 0 || y < 0 {
+                                continue;
+                            }
+                            let at = Point3::new(x as u32, y as u32, position.z);
+                            if let Some(tile) = walls.get_mut(&at) {
+                                if tile.visible && tile.reveal(time.absolute_time_seconds()) {
+                                    println!("Found a secret door at {:?}", (x, y));
+                                    maps.invalidate();
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
@@ -2,7 +2,7 @@ use amethyst::{
     animation::{
         get_animation_set, AnimationCommand, AnimationControlSet, AnimationSet, EndControl,
     },
-    core::{math::Point3, timing::Time, Hidden, Transform},
+    core::{timing::Time, Hidden, Transform},
     derive::SystemDesc,
     ecs::{
         prelude::{System, SystemData},
@@ -11,11 +11,12 @@ use amethyst::{
     input::{InputHandler, StringBindings},
     prelude::Builder,
     renderer::{SpriteRender, Transparent},
-    shred::{Read, ReadExpect},
-    tiles::{Map, MapStorage, TileMap},
+    shred::{Read, ReadExpect, Write},
+    tiles::{Map, TileMap},
 };
+use bracket_pathfinding::prelude::Point;
 use core::time::Duration;
-use sanity_lib::tile::RoomTile;
+use sanity_lib::{map::can_fire, tile::RoomTile};
 
 use crate::resource::Sprited;
 
@@ -24,7 +25,7 @@ pub struct ShootingSystem {
     last_move: Duration,
 }
 
-use direction::CardinalDirection::*;
+use direction::Direction::*;
 
 impl<'a> System<'a> for ShootingSystem {
     type SystemData = (
@@ -40,6 +41,8 @@ impl<'a> System<'a> for ShootingSystem {
         WriteStorage<'a, AnimationControlSet<usize, SpriteRender>>,
         ReadStorage<'a, crate::component::Weapon>,
         Read<'a, crate::state::Sanity>,
+        Write<'a, crate::resource::GameRng>,
+        Read<'a, crate::resource::Settings>,
     );
 
     fn run(
@@ -57,6 +60,8 @@ impl<'a> System<'a> for ShootingSystem {
             mut control_sets,
             weapons,
             sanity_res,
+            mut rng,
+            settings,
         ): Self::SystemData,
     ) {
         if let Some(map_ent) = sanity_res.level.last().unwrap_or(&None) {
@@ -71,46 +76,45 @@ impl<'a> System<'a> for ShootingSystem {
                                 ("shoot_down", South),
                                 ("shoot_left", West),
                                 ("shoot_right", East),
+                                ("shoot_up_left", NorthWest),
+                                ("shoot_up_right", NorthEast),
+                                ("shoot_down_left", SouthWest),
+                                ("shoot_down_right", SouthEast),
                             ] {
-                                if input.action_is_down(shoot_dir.0).unwrap_or(false) {
+                                if settings.moves.allows(shoot_dir.1)
+                                    && input.action_is_down(shoot_dir.0).unwrap_or(false)
+                                {
                                     self.last_move = time.absolute_time();
 
-                                    let spawn_pos = player_pos.clone() + shoot_dir.1;
+                                    // the shot starts on the player and flies off from there
+                                    let c = shoot_dir.1.coord();
+                                    let delta = Point::new(c.x, c.y);
+                                    if can_fire(tilemap, player_pos.xyz(), delta) {
+                                        let w = weapons.get(player.weapon.unwrap()).unwrap();
+                                        lazy.create_entity(&entities)
+                                            .with(Transparent)
+                                            .with(Hidden)
+                                            .with(Transform::from(
+                                                tilemap.to_world(&player_pos.xyz(), None),
+                                            ))
+                                            .with(w.fire(&mut rng.0))
+                                            .with(player_pos.clone())
+                                            .with(crate::component::MovementIntent {
+                                                dir: shoot_dir.1,
+                                                step: 5,
+                                            })
+                                            .with(bullet_res.new_sprite(()))
+                                            .build();
 
-                                    if let Some(tile) = tilemap.get(&spawn_pos.xyz()) {
-                                        if tile.walkable {
-                                            let w = weapons.get(player.weapon.unwrap()).unwrap();
-                                            lazy.create_entity(&entities)
-                                                .with(Transparent)
-                                                .with(Hidden)
-                                                .with(Transform::from(tilemap.to_world(
-                                                    &Point3::new(
-                                                        player_pos.pos.x as u32,
-                                                        player_pos.pos.y as u32,
-                                                        0,
-                                                    ),
-                                                    None,
-                                                )))
-                                                .with(w.fire())
-                                                .with(player_pos.clone())
-                                                .with(crate::component::MovementIntent {
-                                                    dir: shoot_dir.1,
-                                                    step: 5,
-                                                })
-                                                .with(bullet_res.new_sprite(()))
-                                                .build();
-
-                                            let control_set =
-                                                get_animation_set(&mut control_sets, entity)
-                                                    .unwrap();
-                                            control_set.add_animation(
-                                                1,
-                                                &animation_set.get(&2).unwrap(),
-                                                EndControl::Stay,
-                                                1.0,
-                                                AnimationCommand::Start,
-                                            );
-                                        }
+                                        let control_set =
+                                            get_animation_set(&mut control_sets, entity).unwrap();
+                                        control_set.add_animation(
+                                            1,
+                                            &animation_set.get(&2).unwrap(),
+                                            EndControl::Stay,
+                                            1.0,
+                                            AnimationCommand::Start,
+                                        );
                                     }
                                 }
                             }
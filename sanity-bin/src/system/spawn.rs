@@ -1,22 +1,23 @@
 use crate::{
-    component::Position,
-    resource::{Animated, Sprited},
+    component::{item::ItemType, Position},
+    resource::{Animated, EnemySpawn, Purpose, Sprited},
 };
 use amethyst::{
     core::{math::Point3, Hidden, Transform},
     derive::SystemDesc,
     ecs::{
         prelude::{System, SystemData, WriteStorage},
-        Entities, Join, LazyUpdate, ReadStorage,
+        Entities, Entity, Join, LazyUpdate, ReadStorage,
     },
     prelude::*,
     renderer::Transparent,
-    shred::{Read, ReadExpect},
-    tiles::{Map, TileMap},
+    shred::{Read, ReadExpect, Write},
+    tiles::{Map, MapStorage, TileMap},
 };
 use bracket_pathfinding::prelude::*;
-use rand::{seq::SliceRandom, thread_rng};
-use sanity_lib::{map::SanityMap, tile::RoomTile};
+use direction::Coord;
+use rand::seq::SliceRandom;
+use sanity_lib::{assets::SpawnKind, map::SanityMap, regions::TileKind, tile::RoomTile};
 use std::cmp::Ordering;
 
 #[derive(Default, SystemDesc)]
@@ -35,9 +36,12 @@ impl<'a> System<'a> for SpawnSystem {
         ReadStorage<'a, crate::component::Health>,
         ReadStorage<'a, crate::component::Item>,
         ReadExpect<'a, crate::resource::Items>,
-        ReadStorage<'a, crate::component::Exit>,
-        ReadExpect<'a, crate::resource::Exits>,
-        Read<'a, crate::state::Sanity>,
+        Write<'a, crate::state::Sanity>,
+        Write<'a, crate::resource::GameRng>,
+        ReadExpect<'a, crate::resource::Levels>,
+        ReadStorage<'a, crate::component::Regions>,
+        Read<'a, crate::resource::Settings>,
+        Read<'a, crate::resource::DijkstraMaps>,
     );
 
     fn run(
@@ -54,166 +58,202 @@ impl<'a> System<'a> for SpawnSystem {
             healths,
             items,
             items_res,
-            exits,
-            exits_res,
-            sanity_res,
+            mut sanity_res,
+            mut rng,
+            levels,
+            regions,
+            settings,
+            maps,
         ): Self::SystemData,
     ) {
-        let max_enemies = 10;
-        let max_items = 1;
+        let def = match levels.get(sanity_res.level.len()) {
+            Some(def) => def,
+            None => return,
+        };
+        let max_enemies = def.max_enemies;
+        let max_items = def.max_items;
 
-        if (&enemies, &healths).join().count() < max_enemies || (&items).join().count() < max_items
-        {
+        // vault spawn points are used once, as soon as the level is in place
+        let current = sanity_res.level.last().copied().flatten();
+        let storey = (&positions, &players).join().next().map_or(0, |(p, _)| p.z);
+        if let Some((map_ent, map)) = current.and_then(|e| walls.get(e).map(|m| (e, m))) {
+            for (p, kind) in std::mem::take(&mut sanity_res.spawn_points) {
+                match kind {
+                    SpawnKind::Enemy => {
+                        if let Ok(enemy) = def.enemies.choose_weighted(&mut rng.0, |e| e.weight) {
+                            spawn_enemy(
+                                &lazy,
+                                &entities,
+                                map_ent,
+                                map,
+                                (p, storey),
+                                enemy,
+                                &enemies_res,
+                            );
+                        }
+                    }
+                    SpawnKind::Item => {
+                        if let Ok(spawn) = def.items.choose_weighted(&mut rng.0, |i| i.weight) {
+                            spawn_item(
+                                &lazy,
+                                &entities,
+                                map_ent,
+                                map,
+                                (p, storey),
+                                spawn.item,
+                                &items_res,
+                            );
+                        }
+                    }
+                    SpawnKind::Exit => {} // placed along with the level
+                }
+            }
+        }
+
+        // only what is on this level counts, the levels the player left keep theirs
+        let on_level = |p: &Position| Some(p.map) == current;
+        let enemy_count = (&enemies, &positions, &healths)
+            .join()
+            .filter(|(_, p, _)| on_level(p))
+            .count();
+        let item_count = (&items, &positions)
+            .join()
+            .filter(|(_, p)| on_level(p))
+            .count();
+        if enemy_count < max_enemies || item_count < max_items {
             if let Some(map_ent) = sanity_res.level.last().unwrap_or(&None) {
                 if let Some(tilemap) = walls.get_mut(*map_ent) {
-                    let my_map = SanityMap(tilemap);
+                    let my_map = SanityMap(tilemap, settings.moves);
 
                     for (position, _) in (&positions, &players).join() {
-                        let idx = my_map.point2d_to_index(position.pos);
-
-                        let dijkstra = DijkstraMap::new(
-                            my_map.0.dimensions().x,
-                            my_map.0.dimensions().y,
-                            &[idx],
-                            &my_map,
-                            1000.,
-                        );
+                        let dijkstra = match maps.get(*map_ent, Purpose::Player) {
+                            Some(dijkstra) => dijkstra,
+                            None => continue,
+                        };
 
+                        // only the storey the player is on, the others are out of sight
                         let mut near_to_far = dijkstra
                             .map
                             .iter()
                             .map(|x| if x > &1000. { &0. } else { x })
                             .enumerate()
+                            .filter(|(idx, _)| my_map.position(*idx).1 == position.z)
                             .collect::<Vec<(usize, &f32)>>();
 
                         near_to_far
                             .sort_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap_or(Ordering::Equal));
 
-                        let mut rng = thread_rng();
-
                         // TODO: valid locations are hardcoded to 8 squares away
                         //  I would like to specify percentile brackets of distance
                         if let Some(spawnable) = near_to_far.rsplit(|x| *x.1 < 8.).next() {
-                            let enemy_positions: Vec<_> =
-                                (&enemies, &positions, &healths).join().collect();
+                            // enemies stay out of the corridors by the start, items go in dead
+                            // ends while there are any
+                            let level = regions.get(*map_ent).filter(|l| l.storey == position.z);
+                            let at = |pos: &(usize, &f32)| {
+                                let (p, _) = my_map.position(pos.0);
+                                Coord::new(p.x, p.y)
+                            };
+                            let for_enemies: Vec<_> = spawnable
+                                .iter()
+                                .filter(|pos| {
+                                    level.map_or(true, |l| {
+                                        l.regions
+                                            .region(at(pos))
+                                            .map_or(true, |r| !l.guarded.contains(&r.id))
+                                    })
+                                })
+                                .copied()
+                                .collect();
+                            let dead_ends: Vec<_> = spawnable
+                                .iter()
+                                .filter(|pos| {
+                                    level.map_or(false, |l| {
+                                        l.regions.kind(at(pos)) == Some(TileKind::DeadEnd)
+                                    })
+                                })
+                                .copied()
+                                .collect();
+                            let for_items = if dead_ends.len() > max_items {
+                                &dead_ends[..]
+                            } else {
+                                spawnable
+                            };
+
+                            let enemy_positions: Vec<_> = (&enemies, &positions, &healths)
+                                .join()
+                                .filter(|(_, p, _)| on_level(p))
+                                .collect();
                             let mut current_enemies = enemy_positions.len();
 
-                            while spawnable.len() > max_enemies && current_enemies < max_enemies {
-                                let pos = spawnable.choose(&mut rng).unwrap();
-                                let p = my_map.index_to_point2d(pos.0);
+                            while for_enemies.len() > max_enemies && current_enemies < max_enemies {
+                                let pos = for_enemies.choose(&mut rng.0).unwrap();
+                                let (p, z) = my_map.position(pos.0);
 
-                                if enemy_positions.iter().any(|x| x.1.pos == p) {
+                                if enemy_positions.iter().any(|x| x.1.pos == p && x.1.z == z) {
                                     println!("Enemy already at position, trying a new position.");
                                     continue;
                                 }
 
-                                if let Some(tile) = my_map.get(p) {
+                                let enemy =
+                                    match def.enemies.choose_weighted(&mut rng.0, |e| e.weight) {
+                                        Ok(enemy) => enemy,
+                                        Err(_) => break,
+                                    };
+
+                                if let Some(tile) =
+                                    my_map.0.get(&Point3::new(p.x as u32, p.y as u32, z))
+                                {
                                     if tile.walkable {
                                         // should just store dijkstras for every entity that can move
-                                        let w = my_map.0.to_world(
-                                            &Point3::new(p.x as u32, p.y as u32, 0),
-                                            None,
+                                        spawn_enemy(
+                                            &lazy,
+                                            &entities,
+                                            *map_ent,
+                                            my_map.0,
+                                            (p, z),
+                                            enemy,
+                                            &enemies_res,
                                         );
-                                        let mut t = Transform::from(w);
-                                        t.move_up(8.);
-
-                                        lazy.create_entity(&entities)
-                                            .with(crate::component::Enemy)
-                                            .with(Hidden)
-                                            .with(Position {
-                                                pos: p,
-                                                map: *map_ent,
-                                            })
-                                            .with(crate::component::Health {
-                                                max: 20,
-                                                current: 20,
-                                            })
-                                            .with(t)
-                                            .with(enemies_res.new_animated_sprite())
-                                            .build();
-
                                         current_enemies += 1;
-                                        println!("Spawn at {:?}", p);
                                     }
                                 }
                             }
 
-                            let item_positions: Vec<_> = (&items, &positions).join().collect();
+                            let item_positions: Vec<_> = (&items, &positions)
+                                .join()
+                                .filter(|(_, p)| on_level(p))
+                                .collect();
                             let mut current_items = item_positions.len();
 
-                            while spawnable.len() > max_items && current_items < max_items {
-                                let pos = spawnable.choose(&mut rng).unwrap();
-                                let p = my_map.index_to_point2d(pos.0);
+                            while for_items.len() > max_items && current_items < max_items {
+                                let pos = for_items.choose(&mut rng.0).unwrap();
+                                let (p, z) = my_map.position(pos.0);
 
-                                if item_positions.iter().any(|x| x.1.pos == p) {
+                                if item_positions.iter().any(|x| x.1.pos == p && x.1.z == z) {
                                     println!("Item already at position, trying a new position.");
                                     continue;
                                 }
 
-                                if let Some(tile) = my_map.get(p) {
-                                    if tile.walkable {
-                                        // should just store dijkstras for every entity that can move
-                                        let w = my_map.0.to_world(
-                                            &Point3::new(p.x as u32, p.y as u32, 0),
-                                            None,
-                                        );
-
-                                        lazy.create_entity(&entities)
-                                            .with(crate::component::Item {
-                                                item: crate::component::item::ItemType::Flashlight,
-                                            })
-                                            .with(Hidden)
-                                            .with(Position {
-                                                pos: p,
-                                                map: *map_ent,
-                                            })
-                                            .with(Transform::from(w))
-                                            .with(items_res.new_sprite(
-                                                crate::component::item::ItemType::Flashlight,
-                                            ))
-                                            .build();
+                                let item = match def.items.choose_weighted(&mut rng.0, |i| i.weight)
+                                {
+                                    Ok(spawn) => spawn.item,
+                                    Err(_) => break,
+                                };
 
-                                        current_items += 1;
-                                        println!("Spawn item at {:?}", p);
-                                    }
-                                }
-                            }
-
-                            let mut num_exits = (&exits).join().count();
-
-                            while num_exits < 1
-                                && sanity_res.level.len() < crate::state::room::LEVEL_SIZES.len()
-                            {
-                                let pos = near_to_far.last().unwrap(); // furthest point
-                                let p = my_map.index_to_point2d(pos.0);
-                                if item_positions.iter().any(|x| x.1.pos == p) {
-                                    println!(
-                                        "Item already at exit position, trying a new position."
-                                    );
-                                    continue;
-                                }
-                                if let Some(tile) = my_map.get(p) {
+                                if let Some(tile) =
+                                    my_map.0.get(&Point3::new(p.x as u32, p.y as u32, z))
+                                {
                                     if tile.walkable {
-                                        let w = my_map.0.to_world(
-                                            &Point3::new(p.x as u32, p.y as u32, 0),
-                                            None,
+                                        spawn_item(
+                                            &lazy,
+                                            &entities,
+                                            *map_ent,
+                                            my_map.0,
+                                            (p, z),
+                                            item,
+                                            &items_res,
                                         );
-                                        let mut t = Transform::from(w);
-                                        t.move_up(8.);
-
-                                        lazy.create_entity(&entities)
-                                            .with(crate::component::Exit)
-                                            .with(Hidden)
-                                            .with(Position {
-                                                pos: p,
-                                                map: *map_ent,
-                                            })
-                                            .with(t)
-                                            .with(exits_res.new_sprite(()))
-                                            .build();
-
-                                        num_exits += 1;
-                                        println!("Spawn exit at {:?}", p);
+                                        current_items += 1;
                                     }
                                 }
                             }
@@ -224,3 +264,61 @@ impl<'a> System<'a> for SpawnSystem {
         }
     }
 }
+
+fn spawn_enemy(
+    lazy: &LazyUpdate,
+    entities: &Entities<'_>,
+    map_ent: Entity,
+    map: &TileMap<RoomTile>,
+    (p, z): (Point, u32),
+    enemy: &EnemySpawn,
+    enemies_res: &crate::resource::Enemies,
+) {
+    let w = map.to_world(&Point3::new(p.x as u32, p.y as u32, z), None);
+    let mut t = Transform::from(w);
+    t.move_up(8.);
+
+    lazy.create_entity(entities)
+        .with(crate::component::Enemy)
+        .with(Hidden)
+        .with(Position {
+            pos: p,
+            map: map_ent,
+            z,
+        })
+        .with(crate::component::Health {
+            max: enemy.health,
+            current: enemy.health as i32,
+        })
+        .with(t)
+        .with(enemies_res.new_animated_sprite())
+        .build();
+
+    println!("Spawn {} at {:?}", enemy.name, p);
+}
+
+fn spawn_item(
+    lazy: &LazyUpdate,
+    entities: &Entities<'_>,
+    map_ent: Entity,
+    map: &TileMap<RoomTile>,
+    (p, z): (Point, u32),
+    item: ItemType,
+    items_res: &crate::resource::Items,
+) {
+    let w = map.to_world(&Point3::new(p.x as u32, p.y as u32, z), None);
+
+    lazy.create_entity(entities)
+        .with(crate::component::Item { item })
+        .with(Hidden)
+        .with(Position {
+            pos: p,
+            map: map_ent,
+            z,
+        })
+        .with(Transform::from(w))
+        .with(items_res.new_sprite(item))
+        .build();
+
+    println!("Spawn {:?} at {:?}", item, p);
+}
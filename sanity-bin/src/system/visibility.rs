@@ -6,15 +6,15 @@ use amethyst::{
     derive::SystemDesc,
     ecs::{
         prelude::{System, SystemData, WriteStorage},
-        Entities, Join, Read, ReadExpect, ReadStorage,
+        Entities, Join, Read, ReadExpect, ReadStorage, Write,
     },
     renderer::palette,
     tiles::{Map, MapStorage, TileMap},
 };
 use bracket_pathfinding::prelude::{field_of_view_set, Point};
 use sanity_lib::{
-    map::SanityMap,
-    tile::{FloorTile, RoomTile},
+    map::{Moves, SanityMap},
+    tile::{FloorTile, Link, RoomTile, ViewedStorey},
 };
 
 #[derive(Default, SystemDesc)]
@@ -38,6 +38,7 @@ impl<'a> System<'a> for VisibilitySystem {
         Read<'a, AssetStorage<Source>>,
         ReadExpect<'a, Sounds>,
         Option<Read<'a, Output>>,
+        Write<'a, ViewedStorey>,
     );
 
     fn run(
@@ -55,6 +56,7 @@ impl<'a> System<'a> for VisibilitySystem {
             storage,
             sounds,
             audio_output,
+            mut viewed,
         ): Self::SystemData,
     ) {
         if let Some(f_ent) = sanity_res.floor.last().unwrap_or(&None) {
@@ -63,20 +65,46 @@ impl<'a> System<'a> for VisibilitySystem {
                     if let Some(walls) = wall_maps.get_mut(*map_ent) {
                         for (player, position) in (&players, &positions).join() {
                             let dim = *walls.dimensions();
+                            let z = position.z;
+                            viewed.0 = z;
+
                             let mut c = walls.clone();
-                            let my_map = SanityMap(&mut c);
-                            let mut fov = field_of_view_set(position.pos, player.sight(), &my_map);
+                            // sight doesn't depend on how things move
+                            let my_map = SanityMap(&mut c, Moves::Cardinal);
+                            // storeys are stacked in the map, so sight is kept to the player's
+                            let stacked = |p: &crate::component::Position| {
+                                Point::new(p.pos.x, (p.z * dim.y) as i32 + p.pos.y)
+                            };
+                            let mut fov =
+                                field_of_view_set(stacked(position), player.sight(), &my_map);
 
                             for (projectile, position) in (&projectiles, &positions).join() {
-                                let f = field_of_view_set(position.pos, 1, &my_map);
+                                let f = field_of_view_set(stacked(position), 1, &my_map);
                                 fov.extend(&f);
                             }
 
                             for x in 0..dim.x {
                                 for y in 0..dim.y {
-                                    let vis = fov.contains(&Point::new(x, y));
+                                    let vis = fov.contains(&Point::new(x, z * dim.y + y));
+
+                                    // the storey below shows through holes
+                                    let hole = walls
+                                        .get(&Point3::new(x, y, z))
+                                        .map_or(false, |t| t.link == Some(Link::Hole));
+                                    if vis && hole && z > 0 {
+                                        if let Some(tile) = walls.get_mut(&Point3::new(x, y, z - 1))
+                                        {
+                                            tile.visible = true;
+                                            tile.visited = true;
+                                        }
+                                        if let Some(tile) = floor.get_mut(&Point3::new(x, y, z - 1))
+                                        {
+                                            tile.visible = true;
+                                            tile.visited = true;
+                                        }
+                                    }
 
-                                    if let Some(tile) = walls.get_mut(&Point3::new(x, y, 0)) {
+                                    if let Some(tile) = walls.get_mut(&Point3::new(x, y, z)) {
                                         tile.visible = vis;
 
                                         if vis {
@@ -84,14 +112,14 @@ impl<'a> System<'a> for VisibilitySystem {
                                             if !tile.walkable {
                                                 // FIXME: map looks weird unless we can see tile above top wall tile
                                                 if let Some(tile) =
-                                                    walls.get_mut(&Point3::new(x, y - 1, 0))
+                                                    walls.get_mut(&Point3::new(x, y - 1, z))
                                                 {
                                                     tile.visible = vis;
                                                     tile.visited = true;
                                                 }
 
                                                 if let Some(tile) =
-                                                    floor.get_mut(&Point3::new(x, y - 1, 0))
+                                                    floor.get_mut(&Point3::new(x, y - 1, z))
                                                 {
                                                     tile.visible = vis;
                                                     tile.visited = true;
@@ -105,7 +133,7 @@ impl<'a> System<'a> for VisibilitySystem {
                                         }
                                     }
 
-                                    if let Some(tile) = floor.get_mut(&Point3::new(x, y, 0)) {
+                                    if let Some(tile) = floor.get_mut(&Point3::new(x, y, z)) {
                                         tile.visible = vis;
                                         if vis {
                                             tile.visited = true;
@@ -113,7 +141,10 @@ impl<'a> System<'a> for VisibilitySystem {
                                     }
 
                                     for (entity, position) in (&entities, &positions).join() {
-                                        if x == position.pos.x as u32 && y == position.pos.y as u32
+                                        if position.map == *map_ent
+                                            && position.z == z
+                                            && x == position.pos.x as u32
+                                            && y == position.pos.y as u32
                                         {
                                             if vis {
                                                 hiddens.remove(entity);
@@ -133,6 +164,13 @@ impl<'a> System<'a> for VisibilitySystem {
                                     }
                                 }
                             }
+
+                            // nothing on the other storeys is in sight
+                            for (entity, position) in (&entities, &positions).join() {
+                                if position.map == *map_ent && position.z != z {
+                                    hiddens.insert(entity, Hidden);
+                                }
+                            }
                         }
                     }
                 }
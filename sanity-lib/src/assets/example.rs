@@ -0,0 +1,127 @@
+use super::Pairs;
+use amethyst::{
+    assets::{Asset, Format, Handle},
+    ecs::VecStorage,
+    Error,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+
+type TileSetIndex = usize;
+
+/// Bits Tiled sets on a tile id when the tile is flipped or rotated.
+const TILED_FLAGS: u32 = 0xE000_0000;
+
+/// A hand painted level the generator learns its rules from.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Example {
+    pub tiles: Vec<Vec<Option<TileSetIndex>>>, // rows from the top, `None` is empty space
+}
+
+impl Example {
+    pub fn width(&self) -> i32 {
+        self.tiles.iter().map(|row| row.len()).max().unwrap_or(0) as i32
+    }
+
+    pub fn height(&self) -> i32 {
+        self.tiles.len() as i32
+    }
+
+    pub fn get(&self, x: i32, y: i32) -> Option<TileSetIndex> {
+        if x < 0 || y < 0 {
+            return None;
+        }
+
+        self.tiles
+            .get(y as usize)
+            .and_then(|row| row.get(x as usize))
+            .copied()
+            .flatten()
+    }
+
+    /// Reads a layer exported from Tiled as CSV, where 0 is an empty cell and sprites count from 1.
+    pub fn from_tiled_csv(text: &str) -> Result<Self, String> {
+        let tiles = text
+            .lines()
+            .map(|line| line.trim().trim_end_matches(','))
+            .filter(|line| !line.is_empty())
+            .enumerate()
+            .map(|(y, line)| {
+                line.split(',')
+                    .map(|cell| {
+                        let gid = cell.trim().parse::<u32>().map_err(|e| {
+                            format!("row {}: {:?} is not a tile id: {}", y + 1, cell, e)
+                        })?;
+                        Ok(match gid & !TILED_FLAGS {
+                            0 => None,
+                            id => Some(id as TileSetIndex - 1),
+                        })
+                    })
+                    .collect::<Result<Vec<_>, String>>()
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+
+        Ok(Example { tiles })
+    }
+
+    /// How often every sprite is painted.
+    pub fn counts(&self) -> BTreeMap<TileSetIndex, u32> {
+        let mut counts = BTreeMap::new();
+        for tile in self.tiles.iter().flatten().flatten() {
+            *counts.entry(*tile).or_insert(0) += 1;
+        }
+        counts
+    }
+
+    /// A copy of `pairs` whose `ns` and `we` are every adjacency seen in the example.
+    /// Sprites without an explicit weight are weighted by how often they are painted.
+    pub fn learn(&self, pairs: &Pairs) -> Pairs {
+        let mut ns = BTreeSet::new();
+        let mut we = BTreeSet::new();
+
+        for y in 0..self.height() {
+            for x in 0..self.width() {
+                if let Some(tile) = self.get(x, y) {
+                    ns.extend(self.get(x, y + 1).map(|below| (tile, below)));
+                    we.extend(self.get(x + 1, y).map(|right| (tile, right)));
+                }
+            }
+        }
+
+        let mut learned = Pairs {
+            ns: ns.into_iter().collect(),
+            we: we.into_iter().collect(),
+            ..pairs.clone()
+        };
+
+        for (tile, count) in self.counts() {
+            let def = learned.tiles.entry(tile).or_default();
+            def.weight = def.weight.or(Some(count));
+        }
+
+        learned
+    }
+}
+
+pub type ExampleHandle = Handle<Example>;
+
+impl Asset for Example {
+    const NAME: &'static str = "crate::assets::Example";
+    type Data = Self;
+    type HandleStorage = VecStorage<ExampleHandle>;
+}
+
+/// Loads an `Example` from a Tiled CSV export.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TiledCsvFormat;
+
+impl Format<Example> for TiledCsvFormat {
+    fn name(&self) -> &'static str {
+        "TiledCsv"
+    }
+
+    fn import_simple(&self, bytes: Vec<u8>) -> Result<Example, Error> {
+        let text = String::from_utf8(bytes).map_err(Error::new)?;
+        Example::from_tiled_csv(&text).map_err(Error::from_string)
+    }
+}
@@ -1,3 +1,11 @@
+mod example;
 mod pairs;
+mod vault;
 
-pub use self::pairs::{Pairs, PairsHandle};
+pub use self::{
+    example::{Example, ExampleHandle, TiledCsvFormat},
+    pairs::{
+        Pairs, PairsHandle, RenderLayer, Symmetry, TileDef, TileRoles, TileTag, PAIRS_VERSION,
+    },
+    vault::{SpawnKind, Vault, VaultHandle, VaultSpawn},
+};
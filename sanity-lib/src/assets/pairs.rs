@@ -2,14 +2,368 @@ use amethyst::{
     assets::{Asset, Handle},
     ecs::VecStorage,
 };
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 
-#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+type TileSetIndex = usize;
+
+/// Current version of the pairs schema, older files are migrated on load.
+pub const PAIRS_VERSION: u32 = 2;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum TileTag {
+    Wall,
+    Floor,
+    Door,
+    Decoration,
+    Void,   // unwalkable empty space
+    Window, // a wall that can be seen and shot through
+    Pit,    // a hole in the floor that can be seen and shot across
+}
+
+/// Which tilemap a sprite is drawn on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RenderLayer {
+    Floor,
+    Wall,
+}
+
+impl Default for RenderLayer {
+    fn default() -> Self {
+        RenderLayer::Wall
+    }
+}
+
+/// Everything the game needs to know about a single sprite of the tileset.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TileDef {
+    pub name: Option<String>,
+    pub walkable: bool,
+    pub opaque: bool,
+    pub weight: Option<u32>, // generation weight, `None` lets the generator decide
+    pub layer: RenderLayer,
+    pub tags: Vec<TileTag>,
+}
+
+impl Default for TileDef {
+    fn default() -> Self {
+        Self {
+            name: None,
+            walkable: false,
+            opaque: true,
+            weight: None,
+            layer: RenderLayer::default(),
+            tags: vec![],
+        }
+    }
+}
+
+impl TileDef {
+    pub fn has_tag(&self, tag: TileTag) -> bool {
+        self.tags.contains(&tag)
+    }
+}
+
+/// Sprites the game uses for a specific job, so no sprite index is hardcoded.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TileRoles {
+    pub border: Option<TileSetIndex>,    // fills the edge of every level
+    pub start: Option<TileSetIndex>,     // placed under the player
+    pub floor: Option<TileSetIndex>,     // fills the floor layer
+    pub exit: Option<TileSetIndex>,      // sprite of the exit entity
+    pub stairs_up: Option<TileSetIndex>, // sprite of the stairs back to the level above
+    pub secret_door: Option<TileSetIndex>, // what a secret door in a wall looks like once found
+}
+
+/// Tiles that are drawn as transformed copies of each other, so a rule for one holds for the others.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Symmetry {
+    /// The second tile is the first mirrored left to right, a tile can be its own mirror.
+    Mirror(TileSetIndex, TileSetIndex),
+    /// The second tile is the first flipped upside down, a tile can be its own flip.
+    Flip(TileSetIndex, TileSetIndex),
+    /// A tile followed by itself turned 90, 180 and 270 degrees clockwise.
+    Rotations([TileSetIndex; 4]),
+}
+
+type Rules = BTreeSet<(TileSetIndex, TileSetIndex)>;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(from = "PairsFile")]
 pub struct Pairs {
-    pub ns: Vec<(usize, usize)>,
-    pub we: Vec<(usize, usize)>,
-    pub walkable: Vec<usize>,
-    pub null: usize, // unwalkable empty space tile
+    pub version: u32,
+    pub max_tiles: Option<TileSetIndex>, // sprites from this index on are never generated
+    pub roles: TileRoles,
+    pub symmetry: Vec<Symmetry>,
+    pub ns: Vec<(TileSetIndex, TileSetIndex)>,
+    pub we: Vec<(TileSetIndex, TileSetIndex)>,
+    pub tiles: BTreeMap<TileSetIndex, TileDef>,
+}
+
+impl Default for Pairs {
+    fn default() -> Self {
+        Self {
+            version: PAIRS_VERSION,
+            max_tiles: None,
+            roles: TileRoles::default(),
+            symmetry: vec![],
+            ns: vec![],
+            we: vec![],
+            tiles: BTreeMap::new(),
+        }
+    }
+}
+
+impl Pairs {
+    /// Definition for a sprite, tiles that were never described use the default definition.
+    pub fn tile(&self, idx: TileSetIndex) -> TileDef {
+        self.tiles.get(&idx).cloned().unwrap_or_default()
+    }
+
+    pub fn walkable(&self, idx: TileSetIndex) -> bool {
+        self.tiles.get(&idx).map_or(false, |t| t.walkable)
+    }
+
+    pub fn opaque(&self, idx: TileSetIndex) -> bool {
+        self.tiles.get(&idx).map_or(true, |t| t.opaque)
+    }
+
+    pub fn tagged(&self, tag: TileTag) -> impl Iterator<Item = TileSetIndex> + '_ {
+        self.tiles
+            .iter()
+            .filter(move |(_, t)| t.has_tag(tag))
+            .map(|(idx, _)| *idx)
+    }
+
+    /// The tile used for unwalkable empty space.
+    pub fn void(&self) -> Option<TileSetIndex> {
+        self.tagged(TileTag::Void).next()
+    }
+
+    /// Number of sprites taking part in generation, one past the highest index used.
+    pub fn max_tiles(&self) -> TileSetIndex {
+        self.max_tiles.unwrap_or_else(|| {
+            self.ns
+                .iter()
+                .chain(self.we.iter())
+                .flat_map(|&(a, b)| vec![a, b])
+                .chain(self.tiles.keys().copied())
+                .max()
+                .map_or(0, |idx| idx + 1)
+        })
+    }
+
+    pub fn border(&self) -> Option<TileSetIndex> {
+        self.roles.border.or_else(|| self.void())
+    }
+
+    pub fn start(&self) -> Option<TileSetIndex> {
+        self.roles.start.or_else(|| {
+            self.tiles
+                .iter()
+                .find(|(_, t)| t.walkable)
+                .map(|(idx, _)| *idx)
+        })
+    }
+
+    pub fn floor(&self) -> Option<TileSetIndex> {
+        self.roles.floor
+    }
+
+    pub fn exit(&self) -> Option<TileSetIndex> {
+        self.roles
+            .exit
+            .or_else(|| self.tagged(TileTag::Door).next())
+            .or_else(|| self.start())
+    }
+
+    pub fn stairs_up(&self) -> Option<TileSetIndex> {
+        self.roles.stairs_up.or_else(|| self.exit())
+    }
+
+    pub fn secret_door(&self) -> Option<TileSetIndex> {
+        self.roles
+            .secret_door
+            .or_else(|| self.tagged(TileTag::Door).next())
+            .or_else(|| self.start())
+    }
+
+    /// Every rule implied by `ns` and `we` through the declared symmetry.
+    fn closure(&self, ns: Rules, we: Rules) -> (Rules, Rules) {
+        let (mut mirror, mut flip, mut rotate) = (HashMap::new(), HashMap::new(), HashMap::new());
+        for symmetry in &self.symmetry {
+            match *symmetry {
+                Symmetry::Mirror(a, b) => {
+                    mirror.insert(a, b);
+                    mirror.insert(b, a);
+                }
+                Symmetry::Flip(a, b) => {
+                    flip.insert(a, b);
+                    flip.insert(b, a);
+                }
+                Symmetry::Rotations(turns) => {
+                    for i in 0..4 {
+                        rotate.insert(turns[i], turns[(i + 1) % 4]);
+                    }
+                }
+            }
+        }
+        let both = |map: &HashMap<TileSetIndex, TileSetIndex>, a, b| {
+            map.get(&a).and_then(|ma| map.get(&b).map(|mb| (*ma, *mb)))
+        };
+
+        let (mut ns, mut we) = (ns, we);
+        loop {
+            let (mut more_ns, mut more_we) = (vec![], vec![]);
+
+            // `a` is north of `b`
+            for &(a, b) in &ns {
+                more_ns.extend(both(&mirror, a, b));
+                more_ns.extend(both(&flip, b, a));
+                // turning clockwise puts `a` east of `b`
+                more_we.extend(both(&rotate, b, a));
+            }
+
+            // `a` is west of `b`
+            for &(a, b) in &we {
+                more_we.extend(both(&mirror, b, a));
+                more_we.extend(both(&flip, a, b));
+                // turning clockwise puts `a` north of `b`
+                more_ns.extend(both(&rotate, a, b));
+            }
+
+            let known = ns.len() + we.len();
+            ns.extend(more_ns);
+            we.extend(more_we);
+            if ns.len() + we.len() == known {
+                return (ns, we);
+            }
+        }
+    }
+
+    /// Adds the mirrored, flipped and rotated copies of every rule, done when the pairs are loaded.
+    pub fn expand_symmetry(&mut self) {
+        let (ns, we) = self.closure(
+            self.ns.iter().copied().collect(),
+            self.we.iter().copied().collect(),
+        );
+        self.ns = ns.into_iter().collect();
+        self.we = we.into_iter().collect();
+    }
+
+    /// A copy that keeps a single rule out of each set of symmetric copies, for saving.
+    /// Expanding it again gives back every rule.
+    pub fn compacted(&self) -> Self {
+        let (mut covered_ns, mut covered_we) = (Rules::new(), Rules::new());
+        let (mut ns, mut we) = (vec![], vec![]);
+
+        for &rule in &self.ns {
+            if !covered_ns.contains(&rule) {
+                ns.push(rule);
+                let (more_ns, more_we) =
+                    self.closure(Some(rule).into_iter().collect(), Rules::new());
+                covered_ns.extend(more_ns);
+                covered_we.extend(more_we);
+            }
+        }
+
+        for &rule in &self.we {
+            if !covered_we.contains(&rule) {
+                we.push(rule);
+                let (more_ns, more_we) =
+                    self.closure(Rules::new(), Some(rule).into_iter().collect());
+                covered_ns.extend(more_ns);
+                covered_we.extend(more_we);
+            }
+        }
+
+        Pairs {
+            ns,
+            we,
+            ..self.clone()
+        }
+    }
+}
+
+/// On disk representation of every pairs version we know how to load.
+#[derive(Deserialize)]
+struct PairsFile {
+    #[serde(default = "version_one")]
+    version: u32,
+    #[serde(default)]
+    max_tiles: Option<TileSetIndex>,
+    #[serde(default)]
+    roles: TileRoles,
+    #[serde(default)]
+    symmetry: Vec<Symmetry>,
+    #[serde(default)]
+    ns: Vec<(TileSetIndex, TileSetIndex)>,
+    #[serde(default)]
+    we: Vec<(TileSetIndex, TileSetIndex)>,
+    #[serde(default)]
+    tiles: BTreeMap<TileSetIndex, TileDef>,
+    // version 1 only
+    #[serde(default)]
+    walkable: Vec<TileSetIndex>,
+    #[serde(default, deserialize_with = "some")]
+    null: Option<TileSetIndex>,
+}
+
+fn version_one() -> u32 {
+    1
+}
+
+fn some<'de, D: Deserializer<'de>>(d: D) -> Result<Option<TileSetIndex>, D::Error> {
+    TileSetIndex::deserialize(d).map(Some)
+}
+
+impl From<PairsFile> for Pairs {
+    fn from(file: PairsFile) -> Self {
+        let mut pairs = Pairs {
+            version: PAIRS_VERSION,
+            max_tiles: file.max_tiles,
+            roles: file.roles,
+            symmetry: file.symmetry,
+            ns: file.ns,
+            we: file.we,
+            tiles: file.tiles,
+        };
+
+        if file.version < 2 {
+            // v1 only knew about walkable tiles and a single empty tile, everything else is a wall
+            let used = pairs
+                .ns
+                .iter()
+                .chain(pairs.we.iter())
+                .flat_map(|&(a, b)| vec![a, b])
+                .chain(file.walkable.iter().copied())
+                .chain(file.null)
+                .collect::<Vec<_>>();
+
+            for idx in used {
+                let walkable = file.walkable.contains(&idx);
+                let tag = if walkable {
+                    TileTag::Floor
+                } else if Some(idx) == file.null {
+                    TileTag::Void
+                } else {
+                    TileTag::Wall
+                };
+
+                pairs.tiles.entry(idx).or_insert_with(|| TileDef {
+                    walkable,
+                    opaque: !walkable,
+                    tags: vec![tag],
+                    ..Default::default()
+                });
+            }
+        }
+
+        pairs.expand_symmetry();
+        pairs
+    }
 }
 
 pub type PairsHandle = Handle<Pairs>;
@@ -19,3 +373,73 @@ impl Asset for Pairs {
     type Data = Self;
     type HandleStorage = VecStorage<PairsHandle>;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn version_one_is_migrated() {
+        let pairs: Pairs = ron::de::from_str(
+            "(walkable: [1], null: 0, ns: [(0, 1), (1, 1), (1, 2)], we: [(1, 2), (2, 2)])",
+        )
+        .unwrap();
+
+        assert_eq!(pairs.version, PAIRS_VERSION);
+        assert_eq!(pairs.void(), Some(0));
+        assert_eq!(pairs.border(), Some(0));
+        assert_eq!(pairs.start(), Some(1));
+        assert_eq!(pairs.tile(0).tags, vec![TileTag::Void]);
+        assert_eq!(pairs.tile(1).tags, vec![TileTag::Floor]);
+        assert_eq!(pairs.tile(2).tags, vec![TileTag::Wall]);
+        assert!(!pairs.walkable(0));
+        assert!(pairs.walkable(1));
+        assert!(!pairs.walkable(2));
+        assert!(!pairs.opaque(1));
+        assert_eq!(pairs.ns, vec![(0, 1), (1, 1), (1, 2)]);
+        assert_eq!(pairs.we, vec![(1, 2), (2, 2)]);
+    }
+
+    /// The rules `ns` and `we` grow into through `symmetry`.
+    fn expanded(
+        symmetry: Vec<Symmetry>,
+        ns: &[(TileSetIndex, TileSetIndex)],
+        we: &[(TileSetIndex, TileSetIndex)],
+    ) -> (
+        Vec<(TileSetIndex, TileSetIndex)>,
+        Vec<(TileSetIndex, TileSetIndex)>,
+    ) {
+        let mut pairs = Pairs {
+            symmetry,
+            ns: ns.to_vec(),
+            we: we.to_vec(),
+            ..Default::default()
+        };
+        pairs.expand_symmetry();
+        (pairs.ns, pairs.we)
+    }
+
+    #[test]
+    fn mirror_swaps_west_and_east() {
+        let symmetry = vec![Symmetry::Mirror(1, 2), Symmetry::Mirror(3, 3)];
+        let (ns, we) = expanded(symmetry, &[(1, 3)], &[(1, 3)]);
+        assert_eq!(ns, vec![(1, 3), (2, 3)]);
+        assert_eq!(we, vec![(1, 3), (3, 2)]);
+    }
+
+    #[test]
+    fn flip_swaps_north_and_south() {
+        let symmetry = vec![Symmetry::Flip(1, 2), Symmetry::Flip(3, 3)];
+        let (ns, we) = expanded(symmetry, &[(1, 3)], &[(1, 3)]);
+        assert_eq!(ns, vec![(1, 3), (3, 2)]);
+        assert_eq!(we, vec![(1, 3), (2, 3)]);
+    }
+
+    #[test]
+    fn rotations_turn_north_into_east() {
+        let symmetry = vec![Symmetry::Rotations([1, 2, 3, 4])];
+        let (ns, we) = expanded(symmetry, &[(1, 3)], &[]);
+        assert_eq!(ns, vec![(1, 3)]);
+        assert_eq!(we, vec![(4, 2)]);
+    }
+}
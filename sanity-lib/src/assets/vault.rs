@@ -0,0 +1,57 @@
+use amethyst::{
+    assets::{Asset, Handle},
+    ecs::VecStorage,
+};
+use serde::{Deserialize, Serialize};
+
+type TileSetIndex = usize;
+
+/// What appears on a spawn point of a vault.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SpawnKind {
+    Enemy,
+    Item,
+    Exit,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct VaultSpawn {
+    pub at: (i32, i32), // relative to the top left of the vault
+    pub kind: SpawnKind,
+}
+
+/// An authored patch of tiles the generator places before filling in the rest of the level.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Vault {
+    pub name: String,
+    pub tiles: Vec<Vec<Option<TileSetIndex>>>, // rows from the top, `None` is left to the generator
+    #[serde(default)]
+    pub spawns: Vec<VaultSpawn>,
+}
+
+impl Vault {
+    pub fn width(&self) -> i32 {
+        self.tiles.iter().map(|row| row.len()).max().unwrap_or(0) as i32
+    }
+
+    pub fn height(&self) -> i32 {
+        self.tiles.len() as i32
+    }
+
+    /// Every fixed tile as (x, y, sprite), relative to the top left of the vault.
+    pub fn fixed(&self) -> impl Iterator<Item = (i32, i32, TileSetIndex)> + '_ {
+        self.tiles.iter().enumerate().flat_map(|(y, row)| {
+            row.iter()
+                .enumerate()
+                .filter_map(move |(x, tile)| tile.map(|t| (x as i32, y as i32, t)))
+        })
+    }
+}
+
+pub type VaultHandle = Handle<Vault>;
+
+impl Asset for Vault {
+    const NAME: &'static str = "crate::assets::Vault";
+    type Data = Self;
+    type HandleStorage = VecStorage<VaultHandle>;
+}
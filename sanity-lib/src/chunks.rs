@@ -0,0 +1,241 @@
+//! Terrain generated a chunk at a time around the player, for levels too big to generate at once.
+//! Cells are counted from the top left of chunk (0, 0), so chunk (x, y) covers the cells from
+//! `x * size` to `(x + 1) * size` across and the same down.
+
+use crate::{
+    assets::Pairs,
+    mapgen::{self, Budget, GenError, GenStats},
+    tile::{FloorTile, RoomTile},
+};
+use amethyst::{
+    core::math::Point3,
+    tiles::{Map, MapStorage, TileMap},
+};
+use direction::Coord;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::{collections::HashMap, time::Instant};
+
+/// Chunks along each side of the part of the surface kept on the tilemaps.
+pub const WINDOW: i32 = 3;
+
+/// Chunks further than this from the player along either axis are forgotten.
+pub const KEEP: i32 = 4;
+
+pub type ChunkCoord = (i32, i32);
+
+/// Every chunk of a surface generated so far.
+#[derive(Clone, Debug)]
+pub struct Chunks {
+    pub size: u32,
+    pub pairs: Pairs,
+    seed: u64,
+    chunks: HashMap<ChunkCoord, Vec<usize>>, // sprites row by row
+}
+
+impl Chunks {
+    pub fn new(pairs: Pairs, size: u32, seed: u64) -> Self {
+        Chunks {
+            size: size.max(3),
+            pairs,
+            seed,
+            chunks: HashMap::new(),
+        }
+    }
+
+    /// Sprite of a cell, `None` while its chunk isn't generated.
+    pub fn sprite(&self, cell: Coord) -> Option<usize> {
+        let size = self.size as i32;
+        let chunk = (cell.x.div_euclid(size), cell.y.div_euclid(size));
+        let (x, y) = (cell.x.rem_euclid(size), cell.y.rem_euclid(size));
+        self.chunks
+            .get(&chunk)
+            .map(|sprites| sprites[(y * size + x) as usize])
+    }
+
+    /// Generates `chunk` to fit the chunks already around it, `None` when it is already there.
+    /// A chunk that can't be generated is left open so it can still be crossed.
+    pub fn generate(
+        &mut self,
+        chunk: ChunkCoord,
+        budget: Budget,
+    ) -> Option<Result<GenStats, GenError>> {
+        if self.chunks.contains_key(&chunk) {
+            return None;
+        }
+
+        let size = self.size as i32;
+        let (left, top) = (chunk.0 * size, chunk.1 * size);
+        let edges: Vec<(Coord, usize)> = (-1..=size)
+            .flat_map(|y| (-1..=size).map(move |x| Coord::new(x, y)))
+            .filter(|c| c.x == -1 || c.y == -1 || c.x == size || c.y == size)
+            .filter_map(|c| {
+                self.sprite(Coord::new(left + c.x, top + c.y))
+                    .map(|s| (c, s))
+            })
+            .collect();
+
+        // the same chunk of the same run comes out the same as long as its neighbours do
+        let mut rng = StdRng::seed_from_u64(
+            self.seed
+                ^ (chunk.0 as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15)
+                ^ (chunk.1 as u64).wrapping_mul(0xC2B2_AE3D_27D4_EB4F),
+        );
+        let (sprites, outcome) =
+            match mapgen::gen_chunk(&self.pairs, self.size, &edges, budget, &mut rng) {
+                Ok((sprites, stats)) => (sprites, Ok(stats)),
+                Err(e) => (
+                    vec![self.pairs.start().unwrap_or_default(); (size * size) as usize],
+                    Err(e),
+                ),
+            };
+
+        self.chunks.insert(chunk, sprites);
+        Some(outcome)
+    }
+
+    /// Generates the ring of chunks just outside the window around `centre`, so they are there
+    /// before the window moves on to them. Returns how generating each new chunk went.
+    pub fn ring(&mut self, centre: ChunkCoord, budget: Budget) -> Vec<Result<GenStats, GenError>> {
+        ring_around(centre)
+            .filter_map(|chunk| self.generate(chunk, budget))
+            .collect()
+    }
+
+    /// Whether every chunk of the ring around `centre` is generated.
+    pub fn has_ring(&self, centre: ChunkCoord) -> bool {
+        ring_around(centre).all(|chunk| self.chunks.contains_key(&chunk))
+    }
+
+    /// Takes the chunks of `other` that aren't generated here yet, the ones already here win.
+    pub fn merge(&mut self, other: Chunks) {
+        for (chunk, sprites) in other.chunks {
+            self.chunks.entry(chunk).or_insert(sprites);
+        }
+    }
+
+    /// Forgets the chunks more than `KEEP` chunks from `around`, they are generated afresh when
+    /// the player comes back. Returns how many were forgotten.
+    pub fn unload(&mut self, around: ChunkCoord) -> usize {
+        let before = self.chunks.len();
+        self.chunks
+            .retain(|c, _| (c.0 - around.0).abs() <= KEEP && (c.1 - around.1).abs() <= KEEP);
+        before - self.chunks.len()
+    }
+
+    /// Generates the chunks of the window with `origin` as its top left chunk and draws them on
+    /// to the tilemaps, which are `WINDOW` chunks across. Returns how generating each new chunk
+    /// went.
+    pub fn show(
+        &mut self,
+        origin: ChunkCoord,
+        walls: &mut TileMap<RoomTile>,
+        floor: &mut TileMap<FloorTile>,
+        budget: Budget,
+    ) -> Vec<Result<GenStats, GenError>> {
+        let mut outcomes = vec![];
+        for y in 0..WINDOW {
+            for x in 0..WINDOW {
+                outcomes.extend(self.generate((origin.0 + x, origin.1 + y), budget));
+            }
+        }
+
+        let size = self.size as i32;
+        for y in 0..WINDOW * size {
+            for x in 0..WINDOW * size {
+                let at = Point3::new(x as u32, y as u32, 0);
+                let sprite = self.sprite(Coord::new(origin.0 * size + x, origin.1 * size + y));
+                let def = sprite.map(|s| self.pairs.tile(s)).unwrap_or_default();
+
+                if let Some(tile) = walls.get_mut(&at) {
+                    *tile = RoomTile {
+                        sprite,
+                        walkable: def.walkable,
+                        opaque: def.opaque,
+                        ..Default::default()
+                    };
+                }
+                if let Some(tile) = floor.get_mut(&at) {
+                    *tile = FloorTile {
+                        sprite: self.pairs.floor(),
+                        ..Default::default()
+                    };
+                }
+            }
+        }
+
+        outcomes
+    }
+}
+
+/// The chunks just outside the window around `centre`.
+fn ring_around(centre: ChunkCoord) -> impl Iterator<Item = ChunkCoord> {
+    let reach = WINDOW / 2 + 1;
+    (-reach..=reach)
+        .flat_map(move |y| (-reach..=reach).map(move |x| (x, y)))
+        .filter(move |(x, y)| x.abs() == reach || y.abs() == reach)
+        .map(move |(x, y)| (centre.0 + x, centre.1 + y))
+}
+
+/// Builds the first window of a surface level, centered on chunk (0, 0), on to tilemaps
+/// `WINDOW` chunks across. Chunks that fail are left open, so this only fails when every
+/// one of them did.
+pub fn gen_surface(
+    walls: &mut TileMap<RoomTile>,
+    floor: &mut TileMap<FloorTile>,
+    chunks: &mut Chunks,
+    start: Coord,
+    budget: Budget,
+) -> Result<GenStats, GenError> {
+    let started = Instant::now();
+    let outcomes = chunks.show((-1, -1), walls, floor, budget);
+
+    let mut stats = GenStats::default();
+    for outcome in &outcomes {
+        let (attempts, contradictions) = match outcome {
+            Ok(s) => (s.attempts, s.contradictions),
+            Err(e) => {
+                log::info!("Leaving a chunk open: {}", e);
+                (e.attempts, e.contradictions)
+            }
+        };
+        stats.attempts += attempts;
+        stats.contradictions += contradictions;
+    }
+    stats.elapsed = started.elapsed();
+
+    if outcomes.iter().all(Result::is_err) {
+        if let Some(Err(e)) = outcomes.into_iter().next() {
+            return Err(GenError {
+                attempts: stats.attempts,
+                contradictions: stats.contradictions,
+                elapsed: stats.elapsed,
+                ..e
+            });
+        }
+    }
+
+    stats.metrics = crate::metrics::measure(walls, start);
+    let (width, height) = (walls.dimensions().x, walls.dimensions().y);
+    stats.walkable_ratio = stats.metrics.walkable as f32 / (width * height) as f32;
+    Ok(stats)
+}
+
+/// The middle of a chunk at least `min_distance` cells from chunk (0, 0), in a random direction.
+/// Counted from the top left of the window with `origin` as its top left chunk, so it may be
+/// outside of the tilemaps until the player gets near.
+pub fn pick_exit<R: Rng>(size: u32, min_distance: i32, origin: ChunkCoord, rng: &mut R) -> Coord {
+    let size = size.max(3) as i32;
+    let away = ((min_distance + size - 1) / size).max(1);
+    let along = rng.gen_range(-away, away + 1);
+    let chunk = match rng.gen_range(0, 4) {
+        0 => (along, -away),
+        1 => (away, along),
+        2 => (along, away),
+        _ => (-away, along),
+    };
+
+    Coord::new(
+        (chunk.0 - origin.0) * size + size / 2,
+        (chunk.1 - origin.1) * size + size / 2,
+    )
+}
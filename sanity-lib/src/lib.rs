@@ -1,3 +1,8 @@
 pub mod assets;
+pub mod chunks;
+pub mod lint;
 pub mod map;
+pub mod mapgen;
+pub mod metrics;
+pub mod regions;
 pub mod tile;
@@ -0,0 +1,390 @@
+//! Checks a pairs file for mistakes that would otherwise show up as a generator that never finishes.
+
+use crate::assets::{Pairs, TileTag};
+use std::{
+    collections::{BTreeSet, VecDeque},
+    fmt,
+};
+
+type TileSetIndex = usize;
+
+const DIRECTION_NAMES: [&str; 4] = ["north", "east", "south", "west"];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Warning,
+    Error, // generation can't succeed, or the game can't draw the level
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Problem {
+    /// A tile index past the last sprite of the sheet.
+    OutOfSheet { tile: TileSetIndex, sprites: usize },
+    /// No start tile is set and no tile is walkable.
+    NoStart,
+    /// A tile that takes part in generation but has no rule in one direction.
+    NoNeighbour {
+        tile: TileSetIndex,
+        direction: usize,
+    },
+    /// A walkable or void tile that is in no rule, so it can only ever sit next to itself.
+    Unused { tile: TileSetIndex, role: bool },
+    /// A walkable tile that can't touch any walkable tile, so nothing walks on or off it.
+    LonelyWalkable { tile: TileSetIndex, role: bool },
+    /// Tiles no chain of rules leads to from the start tile, they are never generated.
+    Unreachable { tiles: Vec<TileSetIndex> },
+    /// A window or pit that can be walked on or can't be seen through.
+    Mistagged { tile: TileSetIndex, tag: TileTag },
+}
+
+impl Problem {
+    pub fn severity(&self) -> Severity {
+        match self {
+            Problem::OutOfSheet { .. } | Problem::NoStart => Severity::Error,
+            Problem::Unused { role, .. } | Problem::LonelyWalkable { role, .. } if *role => {
+                Severity::Error
+            }
+            _ => Severity::Warning,
+        }
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.severity() == Severity::Error
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Problem::OutOfSheet { tile, sprites } => write!(
+                f,
+                "tile {} is past the end of the sprite sheet, which has {} sprites",
+                tile, sprites
+            ),
+            Problem::NoStart => write!(f, "no start tile in roles and no walkable tile"),
+            Problem::NoNeighbour { tile, direction } => write!(
+                f,
+                "tile {} has no neighbour to the {}",
+                tile, DIRECTION_NAMES[*direction]
+            ),
+            Problem::Unused { tile, role } => write!(
+                f,
+                "tile {}{} is not in any ns or we pair",
+                tile,
+                if *role { " (start or border)" } else { "" }
+            ),
+            Problem::LonelyWalkable { tile, role } => write!(
+                f,
+                "walkable tile {}{} can't be next to any other walkable tile",
+                tile,
+                if *role { " (start)" } else { "" }
+            ),
+            Problem::Unreachable { tiles } => write!(
+                f,
+                "tiles {:?} can't be reached from the start tile through any pair",
+                tiles
+            ),
+            Problem::Mistagged { tile, tag } => write!(
+                f,
+                "tile {} is tagged {:?} but is walkable or opaque",
+                tile, tag
+            ),
+        }
+    }
+}
+
+/// Every tile that may be placed in direction `dir` of `tile`, in the order north, east, south, west.
+fn neighbours(
+    pairs: &Pairs,
+    tile: TileSetIndex,
+    dir: usize,
+) -> impl Iterator<Item = TileSetIndex> + '_ {
+    let (rules, forward) = match dir {
+        0 => (&pairs.ns, false),
+        1 => (&pairs.we, true),
+        2 => (&pairs.ns, true),
+        _ => (&pairs.we, false),
+    };
+    rules.iter().filter_map(move |&(a, b)| match forward {
+        true if a == tile => Some(b),
+        false if b == tile => Some(a),
+        _ => None,
+    })
+}
+
+/// Problems found in `pairs`, errors first. `sprites` is the size of the sprite sheet when known.
+pub fn lint(pairs: &Pairs, sprites: Option<usize>) -> Vec<Problem> {
+    let mut problems = vec![];
+    let max_tiles = pairs.max_tiles();
+    let paired: BTreeSet<TileSetIndex> = pairs
+        .ns
+        .iter()
+        .chain(pairs.we.iter())
+        .flat_map(|&(a, b)| vec![a, b])
+        .collect();
+    let generated: BTreeSet<TileSetIndex> =
+        paired.iter().copied().filter(|t| *t < max_tiles).collect();
+    let roles = [pairs.start(), pairs.border()];
+
+    if let Some(sprites) = sprites {
+        let used: BTreeSet<TileSetIndex> = paired
+            .iter()
+            .copied()
+            .chain(pairs.tiles.keys().copied())
+            .chain(roles.iter().flatten().copied())
+            .chain(pairs.floor())
+            .chain(pairs.exit())
+            .chain(pairs.stairs_up())
+            .chain(pairs.secret_door())
+            .collect();
+        problems.extend(
+            used.into_iter()
+                .filter(|t| *t >= sprites)
+                .map(|tile| Problem::OutOfSheet { tile, sprites }),
+        );
+    }
+
+    if pairs.start().is_none() {
+        problems.push(Problem::NoStart);
+    }
+
+    for &tile in &generated {
+        for direction in 0..4 {
+            if neighbours(pairs, tile, direction).next().is_none() {
+                problems.push(Problem::NoNeighbour { tile, direction });
+            }
+        }
+    }
+
+    for (&tile, def) in pairs.tiles.range(..max_tiles) {
+        let role = roles.contains(&Some(tile));
+        if !paired.contains(&tile) && (def.walkable || def.has_tag(TileTag::Void) || role) {
+            problems.push(Problem::Unused { tile, role });
+        }
+
+        // windows and pits block the way but not the view
+        for &tag in &[TileTag::Window, TileTag::Pit] {
+            if def.has_tag(tag) && (def.walkable || def.opaque) {
+                problems.push(Problem::Mistagged { tile, tag });
+            }
+        }
+    }
+
+    for &tile in generated.iter().filter(|t| pairs.walkable(**t)) {
+        if !(0..4).any(|dir| neighbours(pairs, tile, dir).any(|n| pairs.walkable(n))) {
+            let role = pairs.start() == Some(tile);
+            problems.push(Problem::LonelyWalkable { tile, role });
+        }
+    }
+
+    if let Some(start) = pairs.start() {
+        let mut reached = BTreeSet::new();
+        let mut open = VecDeque::new();
+        reached.insert(start);
+        open.push_back(start);
+        while let Some(tile) = open.pop_front() {
+            for dir in 0..4 {
+                for next in neighbours(pairs, tile, dir) {
+                    if next < max_tiles && reached.insert(next) {
+                        open.push_back(next);
+                    }
+                }
+            }
+        }
+
+        let tiles: Vec<TileSetIndex> = generated.difference(&reached).copied().collect();
+        if !tiles.is_empty() {
+            problems.push(Problem::Unreachable { tiles });
+        }
+    }
+
+    problems.sort_by_key(|p| std::cmp::Reverse(p.severity()));
+    problems
+}
+
+/// Problems found in the `floor_pairs` of a level. They only decorate a level that is already
+/// generated, so they don't need a start tile or walkable tiles.
+pub fn lint_floor(pairs: &Pairs, sprites: Option<usize>) -> Vec<Problem> {
+    lint(pairs, sprites)
+        .into_iter()
+        .filter(|p| !matches!(p, Problem::NoStart | Problem::LonelyWalkable { .. }))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assets::TileDef;
+
+    const FLOOR: TileSetIndex = 0;
+    const WALL: TileSetIndex = 1;
+
+    fn floor() -> TileDef {
+        TileDef {
+            walkable: true,
+            opaque: false,
+            tags: vec![TileTag::Floor],
+            ..Default::default()
+        }
+    }
+
+    /// Lets `a` and `b` be next to each other any way round.
+    fn allow(pairs: &mut Pairs, a: TileSetIndex, b: TileSetIndex) {
+        for &rule in &[(a, b), (b, a)] {
+            pairs.ns.push(rule);
+            pairs.we.push(rule);
+        }
+    }
+
+    /// A floor and a wall that may be next to each other and themselves any way round.
+    fn pairs() -> Pairs {
+        let mut pairs = Pairs::default();
+        pairs.tiles.insert(FLOOR, floor());
+        pairs.tiles.insert(
+            WALL,
+            TileDef {
+                tags: vec![TileTag::Wall],
+                ..Default::default()
+            },
+        );
+        allow(&mut pairs, FLOOR, FLOOR);
+        allow(&mut pairs, FLOOR, WALL);
+        allow(&mut pairs, WALL, WALL);
+        pairs
+    }
+
+    #[test]
+    fn good_pairs_have_no_problems() {
+        assert_eq!(lint(&pairs(), Some(2)), vec![]);
+    }
+
+    #[test]
+    fn tile_past_the_sheet_is_an_error() {
+        let problems = lint(&pairs(), Some(1));
+        assert_eq!(
+            problems,
+            vec![Problem::OutOfSheet {
+                tile: WALL,
+                sprites: 1
+            }]
+        );
+        assert!(problems[0].is_error());
+    }
+
+    #[test]
+    fn no_start_is_an_error() {
+        let mut pairs = pairs();
+        pairs.tiles.get_mut(&FLOOR).unwrap().walkable = false;
+        let problems = lint(&pairs, None);
+        assert_eq!(problems, vec![Problem::NoStart]);
+        assert!(problems[0].is_error());
+    }
+
+    #[test]
+    fn missing_direction_is_a_warning() {
+        let mut pairs = pairs();
+        pairs.we.retain(|&(a, _)| a != WALL);
+        let problems = lint(&pairs, None);
+        assert_eq!(
+            problems,
+            vec![Problem::NoNeighbour {
+                tile: WALL,
+                direction: 1
+            }]
+        );
+        assert!(!problems[0].is_error());
+    }
+
+    #[test]
+    fn unused_walkable_is_an_error_only_for_the_start() {
+        let mut pairs = pairs();
+        pairs.tiles.insert(2, floor());
+        let problems = lint(&pairs, None);
+        assert_eq!(
+            problems,
+            vec![Problem::Unused {
+                tile: 2,
+                role: false
+            }]
+        );
+        assert!(!problems[0].is_error());
+
+        pairs.roles.start = Some(2);
+        let problems = lint(&pairs, None);
+        assert!(problems.contains(&Problem::Unused {
+            tile: 2,
+            role: true
+        }));
+        assert!(problems[0].is_error());
+    }
+
+    #[test]
+    fn lonely_walkable_is_an_error_only_for_the_start() {
+        let mut pairs = pairs();
+        pairs.ns.retain(|&rule| rule != (FLOOR, FLOOR));
+        pairs.we.retain(|&rule| rule != (FLOOR, FLOOR));
+        let problems = lint(&pairs, None);
+        assert_eq!(
+            problems,
+            vec![Problem::LonelyWalkable {
+                tile: FLOOR,
+                role: true
+            }]
+        );
+        assert!(problems[0].is_error());
+
+        // the start gets company from a second floor, a third one is only ever next to walls
+        pairs.tiles.insert(2, floor());
+        pairs.tiles.insert(3, floor());
+        allow(&mut pairs, FLOOR, 2);
+        allow(&mut pairs, 2, WALL);
+        allow(&mut pairs, 3, WALL);
+        let problems = lint(&pairs, None);
+        assert_eq!(
+            problems,
+            vec![Problem::LonelyWalkable {
+                tile: 3,
+                role: false
+            }]
+        );
+        assert!(!problems[0].is_error());
+    }
+
+    #[test]
+    fn unreachable_tiles_are_a_warning() {
+        let mut pairs = pairs();
+        allow(&mut pairs, 2, 3);
+        let problems = lint(&pairs, None);
+        assert_eq!(problems, vec![Problem::Unreachable { tiles: vec![2, 3] }]);
+        assert!(!problems[0].is_error());
+    }
+
+    #[test]
+    fn walkable_window_and_opaque_pit_are_mistagged() {
+        let mut pairs = pairs();
+        pairs.tiles.get_mut(&FLOOR).unwrap().tags = vec![TileTag::Window];
+        pairs.tiles.get_mut(&WALL).unwrap().tags = vec![TileTag::Pit];
+        let problems = lint(&pairs, None);
+        assert_eq!(
+            problems,
+            vec![
+                Problem::Mistagged {
+                    tile: FLOOR,
+                    tag: TileTag::Window
+                },
+                Problem::Mistagged {
+                    tile: WALL,
+                    tag: TileTag::Pit
+                },
+            ]
+        );
+        assert!(problems.iter().all(|p| !p.is_error()));
+    }
+
+    #[test]
+    fn floor_pairs_need_no_start() {
+        let mut pairs = pairs();
+        pairs.tiles.get_mut(&FLOOR).unwrap().walkable = false;
+        assert_eq!(lint_floor(&pairs, None), vec![]);
+    }
+}
@@ -4,42 +4,143 @@ use amethyst::{
     tiles::{Map, MapStorage, TileMap},
 };
 use bracket_pathfinding::prelude::*;
+use direction::Direction;
 
-pub struct SanityMap<'a>(pub &'a mut TileMap<RoomTile>);
+/// Which ways things can step, picked for the whole run.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Moves {
+    Cardinal,
+    Diagonal,
+}
 
-impl<'a> SanityMap<'a> {
-    pub fn get(&self, pt: Point) -> Option<&RoomTile> {
-        self.0.get(&Point3::new(pt.x as u32, pt.y as u32, 0))
+impl Default for Moves {
+    fn default() -> Self {
+        Moves::Cardinal
+    }
+}
+
+impl Moves {
+    pub fn allows(self, dir: Direction) -> bool {
+        let c = dir.coord();
+        self == Moves::Diagonal || c.x == 0 || c.y == 0
     }
 }
 
+/// The walls of a level for pathfinding and field of view. Storeys are laid out one above the
+/// other, so `(x, y)` on storey `z` is the point `(x, z * height + y)`, and stepping on to a
+/// link lands on the storey it leads to.
+pub struct SanityMap<'a>(pub &'a mut TileMap<RoomTile>, pub Moves);
+
 impl<'a> SanityMap<'a> {
+    /// Tile at a point of the storeys laid out one above the other.
+    pub fn get(&self, pt: Point) -> Option<&RoomTile> {
+        let (p, z) = self.unstack(pt);
+        self.0.get(&Point3::new(p.x as u32, p.y as u32, z))
+    }
+
+    pub fn index(&self, pt: Point, z: u32) -> usize {
+        self.point2d_to_index(Point::new(pt.x, z as i32 * self.height() + pt.y))
+    }
+
+    /// Point and storey of an index.
+    pub fn position(&self, idx: usize) -> (Point, u32) {
+        self.unstack(self.index_to_point2d(idx))
+    }
+
+    fn height(&self) -> i32 {
+        self.0.dimensions().y as i32
+    }
+
+    fn unstack(&self, pt: Point) -> (Point, u32) {
+        let height = self.height();
+        (
+            Point::new(pt.x, pt.y.rem_euclid(height)),
+            pt.y.div_euclid(height) as u32,
+        )
+    }
+
     fn valid_exit(&self, loc: Point, delta: Point) -> Option<usize> {
-        let destination = loc + delta;
-        if self.in_bounds(destination) {
-            let idx = self.point2d_to_index(destination);
-
-            match self
-                .0
-                .get(&Point3::new(destination.x as u32, destination.y as u32, 0))
-            {
-                Some(tile) if tile.walkable => Some(idx),
-                _ => None,
-            }
-        } else {
-            None
+        let (from, z) = self.unstack(loc);
+        let destination = from + delta;
+        if !can_step(
+            &*self.0,
+            Point3::new(from.x as u32, from.y as u32, z),
+            delta,
+        ) {
+            return None;
         }
+
+        let at = Point3::new(destination.x as u32, destination.y as u32, z);
+        Some(self.index(destination, landing(&*self.0, at).unwrap_or(z)))
+    }
+}
+
+/// Whether a step of `delta` from `from` lands on a walkable tile of the same storey. A
+/// diagonal step also needs both tiles it passes between to be walkable, so nothing cuts past
+/// the corner of a wall or squeezes between two.
+pub fn can_step(walls: &TileMap<RoomTile>, from: Point3<u32>, delta: Point) -> bool {
+    can_pass(walls, from, delta, |t| t.walkable)
+}
+
+/// Whether a shot can fly `delta` from `from`. Shots go over anything that can be seen through,
+/// like pits and windows, and are stopped by what can't, like smoke, whether it can be walked
+/// through or not.
+pub fn can_fire(walls: &TileMap<RoomTile>, from: Point3<u32>, delta: Point) -> bool {
+    can_pass(walls, from, delta, |t| !t.opaque)
+}
+
+fn can_pass(
+    walls: &TileMap<RoomTile>,
+    from: Point3<u32>,
+    delta: Point,
+    pass: impl Fn(&RoomTile) -> bool,
+) -> bool {
+    let dim = walls.dimensions();
+    let open = |dx: i32, dy: i32| {
+        let (x, y) = (from.x as i32 + dx, from.y as i32 + dy);
+        x >= 0
+            && y >= 0
+            && x < dim.x as i32
+            && y < dim.y as i32
+            && walls
+                .get(&Point3::new(x as u32, y as u32, from.z))
+                .map_or(false, |t| pass(t))
+    };
+
+    open(delta.x, delta.y)
+        && (delta.x == 0 || delta.y == 0 || (open(delta.x, 0) && open(0, delta.y)))
+}
+
+/// Storey something ends up on after stepping on to `at`, down through a link or up from under
+/// a ladder or lift. `None` when it stays on the storey it is on.
+pub fn landing(walls: &TileMap<RoomTile>, at: Point3<u32>) -> Option<u32> {
+    let walkable = |z: u32| {
+        walls
+            .get(&Point3::new(at.x, at.y, z))
+            .map_or(false, |t| t.walkable)
+    };
+
+    if at.z > 0 && walls.get(&at)?.link.is_some() && walkable(at.z - 1) {
+        return Some(at.z - 1);
+    }
+
+    let above = Point3::new(at.x, at.y, at.z + 1);
+    let climbable = at.z + 1 < walls.dimensions().z
+        && walls
+            .get(&above)
+            .and_then(|t| t.link)
+            .map_or(false, |link| link.climbable());
+    if climbable && walkable(at.z + 1) {
+        Some(at.z + 1)
+    } else {
+        None
     }
 }
 
 impl<'a> BaseMap for SanityMap<'a> {
     fn is_opaque(&self, idx: usize) -> bool {
-        let p = self.index_to_point2d(idx);
-        if let Some(tile) = self.0.get(&Point3::new(p.x as u32, p.y as u32, 0)) {
-            !tile.walkable
-        } else {
-            true
-        }
+        self.get(self.index_to_point2d(idx))
+            .map_or(true, |tile| tile.opaque)
     }
 
     fn get_available_exits(&self, idx: usize) -> SmallVec<[(usize, f32); 10]> {
@@ -58,31 +159,40 @@ impl<'a> BaseMap for SanityMap<'a> {
         if let Some(idx) = self.valid_exit(location, Point::new(0, 1)) {
             exits.push((idx, 1.0))
         }
-        /*
-                if let Some(idx) = self.valid_exit(location, Point::new(-1, -1)) {
-                    exits.push((idx, 1.4))
-                }
-                if let Some(idx) = self.valid_exit(location, Point::new(1, -1)) {
-                    exits.push((idx, 1.4))
-                }
-                if let Some(idx) = self.valid_exit(location, Point::new(-1, 1)) {
-                    exits.push((idx, 1.4))
-                }
-                if let Some(idx) = self.valid_exit(location, Point::new(1, 1)) {
-                    exits.push((idx, 1.4))
-                }
-        */
+        if self.1 == Moves::Diagonal {
+            if let Some(idx) = self.valid_exit(location, Point::new(-1, -1)) {
+                exits.push((idx, 1.4))
+            }
+            if let Some(idx) = self.valid_exit(location, Point::new(1, -1)) {
+                exits.push((idx, 1.4))
+            }
+            if let Some(idx) = self.valid_exit(location, Point::new(-1, 1)) {
+                exits.push((idx, 1.4))
+            }
+            if let Some(idx) = self.valid_exit(location, Point::new(1, 1)) {
+                exits.push((idx, 1.4))
+            }
+        }
         exits
     }
 
     fn get_pathing_distance(&self, idx1: usize, idx2: usize) -> f32 {
-        DistanceAlg::Manhattan.distance2d(self.index_to_point2d(idx1), self.index_to_point2d(idx2))
+        let ((a, a_z), (b, b_z)) = (self.position(idx1), self.position(idx2));
+        let storeys = (a_z as f32 - b_z as f32).abs();
+        match self.1 {
+            Moves::Cardinal => DistanceAlg::Manhattan.distance2d(a, b) + storeys,
+            Moves::Diagonal => {
+                // same costs as the exits, 1.4 for every diagonal step
+                let (dx, dy) = ((a.x - b.x).abs() as f32, (a.y - b.y).abs() as f32);
+                dx.max(dy) + 0.4 * dx.min(dy) + storeys
+            }
+        }
     }
 }
 
 impl<'a> Algorithm2D for SanityMap<'a> {
     fn dimensions(&self) -> Point {
         let dim = self.0.dimensions();
-        Point::new(dim.x, dim.y)
+        Point::new(dim.x, dim.y * dim.z)
     }
 }
@@ -0,0 +1,2015 @@
+use crate::{
+    assets::{Example, RenderLayer, SpawnKind, TileTag, Vault},
+    map::SanityMap,
+    metrics::{Metrics, Thresholds},
+    tile::{FloorTile, Link, RoomTile},
+};
+use amethyst::{
+    core::math::Point3,
+    tiles::{Map, MapStorage, TileMap},
+};
+use bracket_pathfinding::prelude::*;
+use direction::Coord;
+use rand::{seq::SliceRandom, Rng};
+use serde::{Deserialize, Serialize};
+use std::{
+    cell::Cell,
+    collections::{HashMap, HashSet, VecDeque},
+    fmt,
+    num::NonZeroU32,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+use wfc::{overlapping::OverlappingPatterns, PatternDescription, PatternTable, *};
+
+struct ForbidCorner<'a> {
+    width: i32,
+    height: i32,
+    start: Coord,
+    sprites: &'a [usize], // sprite drawn for every pattern
+    border_tile: Option<usize>,
+    start_tile: Option<usize>,
+    fixed: &'a [(Coord, usize)], // vault tiles
+    corridor: Vec<Coord>,        // cells between entrance and exit that must stay walkable
+    solid: Vec<Coord>,           // cells that must not be walkable
+    blocked: &'a [PatternId],    // every pattern that can't be walked on
+    open: &'a [PatternId],       // every pattern that can
+    contradiction: &'a Cell<bool>,
+}
+impl<'a> ForbidCorner<'a> {
+    /// Leaves only the patterns that draw `sprite` at `coord`.
+    fn only<W: Wrap, R: Rng>(
+        &self,
+        fi: &mut ForbidInterface<W>,
+        coord: Coord,
+        sprite: usize,
+        rng: &mut R,
+    ) {
+        let (keep, others): (Vec<PatternId>, Vec<PatternId>) =
+            (0..self.sprites.len() as PatternId).partition(|p| self.sprites[*p as usize] == sprite);
+
+        let failed = match keep[..] {
+            // with plain pairs every sprite is a single pattern
+            [pattern] => fi.forbid_all_patterns_except(coord, pattern, rng).is_err(),
+            _ => others
+                .into_iter()
+                .any(|pattern| fi.forbid_pattern(coord, pattern, rng).is_err()),
+        };
+        if failed {
+            self.contradiction.set(true);
+        }
+    }
+
+    fn walkable<W: Wrap, R: Rng>(&self, fi: &mut ForbidInterface<W>, coord: Coord, rng: &mut R) {
+        self.forbid_all(fi, coord, self.blocked, rng);
+    }
+
+    fn solid<W: Wrap, R: Rng>(&self, fi: &mut ForbidInterface<W>, coord: Coord, rng: &mut R) {
+        self.forbid_all(fi, coord, self.open, rng);
+    }
+
+    fn forbid_all<W: Wrap, R: Rng>(
+        &self,
+        fi: &mut ForbidInterface<W>,
+        coord: Coord,
+        patterns: &[PatternId],
+        rng: &mut R,
+    ) {
+        for pattern in patterns {
+            if fi.forbid_pattern(coord, *pattern, rng).is_err() {
+                self.contradiction.set(true);
+                return;
+            }
+        }
+    }
+}
+impl<'a> ForbidPattern for ForbidCorner<'a> {
+    fn forbid<W: Wrap, R: Rng>(&mut self, fi: &mut ForbidInterface<W>, rng: &mut R) {
+        if let Some(border) = self.border_tile {
+            for x in 0..self.width {
+                self.only(fi, Coord::new(x, 0), border, rng);
+                self.only(fi, Coord::new(x, self.height - 1), border, rng);
+            }
+
+            for y in 0..self.height {
+                self.only(fi, Coord::new(0, y), border, rng);
+                self.only(fi, Coord::new(self.width - 1, y), border, rng);
+            }
+        }
+
+        if let Some(start) = self.start_tile {
+            self.only(fi, self.start, start, rng);
+        }
+
+        for (coord, sprite) in self.fixed {
+            self.only(fi, *coord, *sprite, rng);
+        }
+
+        // a walkable skeleton from entrance to exit, wfc fills in the walls around it
+        for coord in &self.corridor {
+            let taken = (*coord == self.start && self.start_tile.is_some())
+                || self.fixed.iter().any(|(c, _)| c == coord);
+            if !taken {
+                self.walkable(fi, *coord, rng);
+            }
+        }
+
+        for coord in &self.solid {
+            self.solid(fi, *coord, rng);
+        }
+    }
+}
+
+/// Keeps decorations of the floor pass to the cells the player can walk on.
+struct ForbidDecor<'a> {
+    cells: &'a [(Coord, Vec<PatternId>)], // patterns each cell can't have
+    contradiction: &'a Cell<bool>,
+}
+impl<'a> ForbidPattern for ForbidDecor<'a> {
+    fn forbid<W: Wrap, R: Rng>(&mut self, fi: &mut ForbidInterface<W>, rng: &mut R) {
+        for (coord, patterns) in self.cells {
+            for pattern in patterns {
+                if fi.forbid_pattern(*coord, *pattern, rng).is_err() {
+                    self.contradiction.set(true);
+                    return;
+                }
+            }
+        }
+    }
+}
+
+/// Picks an exit inside the border at least `min_distance` steps from the entrance.
+/// Falls back to the farthest cell when the map is too small for that.
+pub fn pick_exit<R: Rng>(
+    width: u32,
+    height: u32,
+    entrance: Coord,
+    min_distance: i32,
+    rng: &mut R,
+) -> Coord {
+    let distance = |c: &Coord| (c.x - entrance.x).abs() + (c.y - entrance.y).abs();
+    let inside: Vec<Coord> = (1..width as i32 - 1)
+        .flat_map(|x| (1..height as i32 - 1).map(move |y| Coord::new(x, y)))
+        .collect();
+
+    let far_enough: Vec<Coord> = inside
+        .iter()
+        .copied()
+        .filter(|c| distance(c) >= min_distance)
+        .collect();
+
+    far_enough
+        .choose(rng)
+        .copied()
+        .or_else(|| inside.iter().copied().max_by_key(distance))
+        .unwrap_or(entrance)
+}
+
+/// Places tried for each vault before it is left out.
+const VAULT_TRIES: usize = 20;
+
+/// A vault and where its top left corner ended up.
+#[derive(Clone, Debug)]
+pub struct PlacedVault {
+    pub at: Coord,
+    pub vault: Vault,
+}
+
+impl PlacedVault {
+    pub fn fixed(&self) -> impl Iterator<Item = (Coord, usize)> + '_ {
+        self.vault
+            .fixed()
+            .map(move |(x, y, t)| (Coord::new(self.at.x + x, self.at.y + y), t))
+    }
+
+    pub fn spawns(&self) -> impl Iterator<Item = (Coord, SpawnKind)> + '_ {
+        self.vault
+            .spawns
+            .iter()
+            .map(move |s| (Coord::new(self.at.x + s.at.0, self.at.y + s.at.1), s.kind))
+    }
+}
+
+fn overlaps(at: Coord, size: (i32, i32), other: Coord, other_size: (i32, i32)) -> bool {
+    at.x < other.x + other_size.0
+        && other.x < at.x + size.0
+        && at.y < other.y + other_size.1
+        && other.y < at.y + size.1
+}
+
+/// Finds room inside the border for each vault, clear of `keep_clear` and of the other vaults.
+/// Vaults that don't fit are left out.
+pub fn place_vaults<R: Rng>(
+    vaults: &[Vault],
+    width: u32,
+    height: u32,
+    keep_clear: &[Coord],
+    rng: &mut R,
+) -> Vec<PlacedVault> {
+    let mut placed: Vec<PlacedVault> = vec![];
+
+    for vault in vaults {
+        let size = (vault.width(), vault.height());
+        let (max_x, max_y) = (width as i32 - 1 - size.0, height as i32 - 1 - size.1);
+
+        let spot = (0..VAULT_TRIES)
+            .filter(|_| max_x >= 1 && max_y >= 1)
+            .map(|_| Coord::new(rng.gen_range(1, max_x + 1), rng.gen_range(1, max_y + 1)))
+            .find(|at| {
+                // a ring of free cells around every vault lets the generator join it up
+                let margin = Coord::new(at.x - 1, at.y - 1);
+                let ringed = (size.0 + 2, size.1 + 2);
+                !keep_clear
+                    .iter()
+                    .any(|c| overlaps(margin, ringed, *c, (1, 1)))
+                    && !placed.iter().any(|p| {
+                        overlaps(margin, ringed, p.at, (p.vault.width(), p.vault.height()))
+                    })
+            });
+
+        match spot {
+            Some(at) => placed.push(PlacedVault {
+                at,
+                vault: vault.clone(),
+            }),
+            None => log::info!("No room for vault {}", vault.name),
+        }
+    }
+
+    placed
+}
+
+/// The exit spawn point of the first vault that has one.
+pub fn vault_exit(vaults: &[PlacedVault]) -> Option<Coord> {
+    vaults
+        .iter()
+        .flat_map(|v| v.spawns())
+        .find(|(_, kind)| *kind == SpawnKind::Exit)
+        .map(|(at, _)| at)
+}
+
+/// A random monotone walk from `from` to `to`, every step is 4-connected to the last.
+fn corridor<R: Rng>(from: Coord, to: Coord, rng: &mut R) -> Vec<Coord> {
+    let mut path = vec![from];
+    let mut at = from;
+
+    while at != to {
+        let step_x = at.x != to.x && (at.y == to.y || rng.gen::<bool>());
+        if step_x {
+            at.x += (to.x - at.x).signum();
+        } else {
+            at.y += (to.y - at.y).signum();
+        }
+        path.push(at);
+    }
+
+    path
+}
+
+/// Where `gen_map` gets the patterns it feeds to wfc from.
+#[derive(Clone, Debug)]
+pub enum Model {
+    /// Single sprites, constrained by the `ns` and `we` entries of the pairs.
+    Pairs,
+    /// Every `size` by `size` window of a hand painted example, kept as often as it appears.
+    Overlapping { example: Example, size: NonZeroU32 },
+}
+
+impl Default for Model {
+    fn default() -> Self {
+        Model::Pairs
+    }
+}
+
+/// How the walls of a level are laid out.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Generator {
+    /// Wave function collapse over the whole map, see `gen_map`.
+    Wfc,
+    /// Rooms split out of the map and joined by corridors, then filled in, see `gen_rooms`.
+    Rooms { min_size: u32, max_size: u32 },
+    /// Unbounded terrain generated a chunk at a time as the player walks, see `chunks`.
+    Surface { chunk_size: u32 },
+}
+
+impl Generator {
+    /// Size of the tilemaps a level is played on, surface levels only keep the chunks around
+    /// the player on them.
+    pub fn map_size(&self, size: (u32, u32)) -> (u32, u32) {
+        match self {
+            Generator::Surface { chunk_size } => {
+                let side = (*chunk_size).max(3) * crate::chunks::WINDOW as u32;
+                (side, side)
+            }
+            _ => size,
+        }
+    }
+}
+
+impl Default for Generator {
+    fn default() -> Self {
+        Generator::Wfc
+    }
+}
+
+/// `PatternDescription` isn't `Clone`.
+fn copy_description(description: &PatternDescription) -> PatternDescription {
+    PatternDescription::new(description.weight, description.allowed_neighbours.clone())
+}
+
+/// The patterns of a model and the sprite each one draws on its cell.
+struct Patterns {
+    descriptions: Vec<PatternDescription>,
+    sprites: Vec<usize>,
+}
+
+impl Patterns {
+    fn new(model: &Model, rules: &[Rule], pairs: &crate::assets::Pairs) -> Self {
+        match model {
+            Model::Pairs => Patterns {
+                descriptions: gen_patterns(rules),
+                sprites: (0..rules.len()).collect(),
+            },
+            Model::Overlapping { example, size } => {
+                // empty cells of the example count as void
+                let empty = pairs.void().or_else(|| pairs.border()).unwrap_or(0);
+                let grid = grid_2d::Grid::new_fn(
+                    grid_2d::Size::new(example.width() as u32, example.height() as u32),
+                    |c| example.get(c.x, c.y).unwrap_or(empty),
+                );
+                let overlapping = OverlappingPatterns::new_original_orientation(grid, *size);
+                let descriptions = overlapping.pattern_descriptions();
+                let sprites = (0..descriptions.len() as PatternId)
+                    .map(|p| *overlapping.pattern_top_left_value(p))
+                    .collect();
+
+                Patterns {
+                    descriptions: descriptions.iter().map(copy_description).collect(),
+                    sprites,
+                }
+            }
+        }
+    }
+
+    /// A fresh table of the descriptions for a run, wfc takes its own.
+    fn table(&self) -> PatternTable<PatternDescription> {
+        self.descriptions.iter().map(copy_description).collect()
+    }
+
+    fn has(&self, sprite: usize) -> bool {
+        self.sprites.contains(&sprite)
+    }
+
+    /// The patterns that can be walked on and the ones that can't.
+    fn by_walkable(&self, pairs: &crate::assets::Pairs) -> (Vec<PatternId>, Vec<PatternId>) {
+        (0..self.sprites.len() as PatternId)
+            .partition(|p| pairs.walkable(self.sprites[*p as usize]))
+    }
+}
+
+/// Limits on how long `gen_map` keeps trying before giving up, and what it accepts.
+#[derive(Clone, Copy, Debug)]
+pub struct Budget {
+    pub max_attempts: usize,
+    pub time_limit: Duration,
+    pub min_walkable_ratio: f32, // share of the map that has to be reachable from the start
+    pub thresholds: Thresholds,  // layouts outside of these are thrown away
+}
+
+impl Default for Budget {
+    fn default() -> Self {
+        Self {
+            max_attempts: 50,
+            time_limit: Duration::from_secs(10),
+            min_walkable_ratio: 0.5,
+            thresholds: Thresholds::default(),
+        }
+    }
+}
+
+/// Attempts made so far, shared with whoever is waiting on the generation.
+#[derive(Clone, Debug, Default)]
+pub struct Progress(Arc<AtomicUsize>);
+
+impl Progress {
+    pub fn attempts(&self) -> usize {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// How a successful generation went.
+#[derive(Clone, Debug, Default)]
+pub struct GenStats {
+    pub attempts: usize,
+    pub contradictions: usize,
+    pub walkable_ratio: f32,
+    pub joined_regions: usize,
+    pub removed_regions: usize,
+    pub metrics: Metrics,
+    pub elapsed: Duration,
+}
+
+/// Why generation gave up, enough to tell a bad pairs file from bad luck.
+#[derive(Clone, Debug, Default)]
+pub struct GenError {
+    pub attempts: usize,
+    pub contradictions: usize,
+    pub best_walkable_ratio: f32,
+    pub rejected: usize, // maps that were connected but missed the quality thresholds
+    pub unplaced: Vec<usize>, // tiles that were never chosen by any attempt
+    pub elapsed: Duration,
+}
+
+impl fmt::Display for GenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "map generation gave up after {} attempts in {:.1}s: {} contradictions, best walkable ratio {:.0}%, {} below the quality thresholds, tiles never placed {:?}",
+            self.attempts,
+            self.elapsed.as_secs_f32(),
+            self.contradictions,
+            self.best_walkable_ratio * 100.,
+            self.rejected,
+            self.unplaced
+        )
+    }
+}
+
+impl std::error::Error for GenError {}
+
+fn to_vec(p: &[(usize, usize)], idx: usize, max: usize) -> (Vec<u32>, Vec<u32>) {
+    (
+        p.iter()
+            .filter(|p| p.1 == idx && p.0 < max)
+            .map(|p| p.0 as u32)
+            .collect(),
+        p.iter()
+            .filter(|p| p.0 == idx && p.1 < max)
+            .map(|p| p.1 as u32)
+            .collect(),
+    )
+}
+
+/// Weight of a tile with no weight of its own and a full set of neighbours.
+pub const DEFAULT_WEIGHT: u32 = 50;
+
+/// What a single tile may be placed next to, in the order north, east, south, west.
+struct Rule {
+    allowed: [Vec<u32>; 4],
+    weight: Option<std::num::NonZeroU32>,
+}
+
+fn gen_rules(pairs: &crate::assets::Pairs) -> Vec<Rule> {
+    let mut rules = Vec::new();
+
+    let max_tiles = pairs.max_tiles();
+
+    for idx in 0..max_tiles {
+        let (mut n, mut s) = to_vec(&pairs.ns, idx, max_tiles);
+        let (mut w, mut e) = to_vec(&pairs.we, idx, max_tiles);
+
+        let mut wt = std::num::NonZeroU32::new(DEFAULT_WEIGHT);
+
+        if (!n.is_empty() || !s.is_empty()) && (w.is_empty() || e.is_empty()) {
+            w.push(idx as u32);
+            e.push(idx as u32);
+            wt = std::num::NonZeroU32::new(1);
+        }
+
+        if (n.is_empty() || s.is_empty()) && (!w.is_empty() || !e.is_empty()) {
+            n.push(idx as u32);
+            s.push(idx as u32);
+            wt = std::num::NonZeroU32::new(1);
+        }
+
+        // an explicit weight on the tile definition always wins over the heuristic
+        if let Some(weight) = pairs.tile(idx).weight {
+            wt = std::num::NonZeroU32::new(weight);
+        }
+
+        if !s.is_empty() || !e.is_empty() || !n.is_empty() || !w.is_empty() {
+            rules.push(Rule {
+                allowed: [n, e, s, w],
+                weight: wt,
+            });
+        } else {
+            // workaround for tiles with no matchers, we just state they can only match with self
+            // limitation of wfc library is that every pattern index is considered
+            rules.push(Rule {
+                allowed: [
+                    vec![idx as u32],
+                    vec![idx as u32],
+                    vec![idx as u32],
+                    vec![idx as u32],
+                ],
+                weight: wt,
+            })
+        }
+    }
+
+    rules
+}
+
+fn gen_patterns(rules: &[Rule]) -> Vec<PatternDescription> {
+    rules
+        .iter()
+        .map(|rule| {
+            PatternDescription::new(
+                rule.weight,
+                direction::CardinalDirectionTable::new_array(rule.allowed.clone()),
+            )
+        })
+        .collect()
+}
+
+/// Whether sprite `b` may be placed in direction `dir` of sprite `a`.
+fn allowed(rules: &[Rule], a: usize, dir: usize, b: usize) -> bool {
+    rules
+        .get(a)
+        .map_or(false, |rule| rule.allowed[dir].contains(&(b as u32)))
+}
+
+/// Offsets in the same order as `Rule::allowed`.
+const DIRECTIONS: [(i32, i32); 4] = [(0, -1), (1, 0), (0, 1), (-1, 0)];
+
+/// Copy of a collapsed map the connectivity pass can freely rework.
+struct Layout {
+    width: i32,
+    height: i32,
+    sprites: Vec<Option<usize>>,
+    walkable: Vec<bool>,
+    locked: Vec<bool>, // vault tiles, never carved or removed
+    changed: Vec<usize>,
+}
+
+impl Layout {
+    fn read(walls: &TileMap<RoomTile>, locked: &[Coord]) -> Self {
+        let (width, height) = (walls.dimensions().x as i32, walls.dimensions().y as i32);
+        let mut layout = Layout {
+            width,
+            height,
+            sprites: vec![None; (width * height) as usize],
+            walkable: vec![false; (width * height) as usize],
+            locked: vec![false; (width * height) as usize],
+            changed: vec![],
+        };
+
+        for c in locked {
+            if layout.in_bounds(*c) {
+                let idx = layout.idx(*c);
+                layout.locked[idx] = true;
+            }
+        }
+
+        for idx in 0..layout.sprites.len() {
+            let c = layout.coord(idx);
+            if let Some(tile) = walls.get(&Point3::new(c.x as u32, c.y as u32, 0)) {
+                layout.sprites[idx] = tile.sprite;
+                layout.walkable[idx] = tile.walkable;
+            }
+        }
+
+        layout
+    }
+
+    fn write(&self, walls: &mut TileMap<RoomTile>, pairs: &crate::assets::Pairs) {
+        for &idx in &self.changed {
+            let c = self.coord(idx);
+            if let Some(tile) = walls.get_mut(&Point3::new(c.x as u32, c.y as u32, 0)) {
+                tile.sprite = self.sprites[idx];
+                tile.walkable = self.walkable[idx];
+                tile.opaque = tile.sprite.map_or(true, |s| pairs.opaque(s));
+            }
+        }
+    }
+
+    fn set(&mut self, idx: usize, sprite: Option<usize>, pairs: &crate::assets::Pairs) {
+        self.sprites[idx] = sprite;
+        self.walkable[idx] = sprite.map_or(false, |s| pairs.walkable(s));
+        self.changed.push(idx);
+    }
+
+    fn idx(&self, c: Coord) -> usize {
+        (c.y * self.width + c.x) as usize
+    }
+
+    fn coord(&self, idx: usize) -> Coord {
+        Coord::new(idx as i32 % self.width, idx as i32 / self.width)
+    }
+
+    fn in_bounds(&self, c: Coord) -> bool {
+        c.x >= 0 && c.y >= 0 && c.x < self.width && c.y < self.height
+    }
+
+    /// Inside the border, the border itself is never carved or removed.
+    fn inside(&self, c: Coord) -> bool {
+        c.x > 0 && c.y > 0 && c.x < self.width - 1 && c.y < self.height - 1
+    }
+
+    fn neighbours(&self, c: Coord) -> impl Iterator<Item = (usize, Coord)> + '_ {
+        DIRECTIONS
+            .iter()
+            .enumerate()
+            .map(move |(dir, (dx, dy))| (dir, Coord::new(c.x + dx, c.y + dy)))
+            .filter(move |(_, n)| self.in_bounds(*n))
+    }
+
+    fn around(&self, c: Coord) -> impl Iterator<Item = Coord> + '_ {
+        (-1..=1)
+            .flat_map(move |dx| (-1..=1).map(move |dy| Coord::new(c.x + dx, c.y + dy)))
+            .filter(move |n| *n != c && self.in_bounds(*n))
+    }
+
+    /// Labels every walkable cell with its region, 4-connected like movement.
+    fn regions(&self) -> Vec<Option<usize>> {
+        let mut label = vec![None; self.walkable.len()];
+        let mut count = 0;
+
+        for first in 0..label.len() {
+            if !self.walkable[first] || label[first].is_some() {
+                continue;
+            }
+
+            label[first] = Some(count);
+            let mut stack = vec![first];
+            while let Some(idx) = stack.pop() {
+                for (_, c) in self.neighbours(self.coord(idx)) {
+                    let next = self.idx(c);
+                    if self.walkable[next] && label[next].is_none() {
+                        label[next] = Some(count);
+                        stack.push(next);
+                    }
+                }
+            }
+            count += 1;
+        }
+
+        label
+    }
+
+    /// Whether `sprite` at `at` agrees with every neighbour except the one we came `from`,
+    /// counting the cells `carved` earlier on the same path with their new sprites.
+    fn fits(
+        &self,
+        rules: &[Rule],
+        at: Coord,
+        sprite: usize,
+        from: Coord,
+        carved: &HashMap<usize, usize>,
+    ) -> bool {
+        self.neighbours(at).all(|(dir, c)| {
+            let idx = self.idx(c);
+            let placed = carved.get(&idx).copied().or(self.sprites[idx]);
+            c == from || placed.map_or(true, |s| allowed(rules, sprite, dir, s))
+        })
+    }
+
+    /// Shortest corridor of walkable sprites from `region` to the `main` region,
+    /// as (cell, sprite) pairs that all agree with the adjacency rules.
+    fn shortest_carve(
+        &self,
+        region: &[usize],
+        regions: &[Option<usize>],
+        main: Option<usize>,
+        carve_with: &[usize],
+        rules: &[Rule],
+    ) -> Option<Vec<(usize, usize)>> {
+        // a node is a cell together with the sprite it would be carved to
+        let node = |idx: usize, k: usize| idx * carve_with.len() + k;
+        let mut parent: HashMap<usize, Option<usize>> = HashMap::new();
+        let mut queue: VecDeque<(Coord, usize, Option<usize>)> = region
+            .iter()
+            .filter_map(|&idx| self.sprites[idx].map(|s| (self.coord(idx), s, None)))
+            .collect();
+
+        while let Some((at, sprite, from)) = queue.pop_front() {
+            let carved: HashMap<usize, usize> =
+                self.trail(from, &parent, carve_with).into_iter().collect();
+            for (dir, c) in self.neighbours(at) {
+                let idx = self.idx(c);
+                if regions[idx] == main {
+                    if self.sprites[idx].map_or(false, |s| allowed(rules, sprite, dir, s)) {
+                        if let Some(path) = self.path(from, &parent, carve_with) {
+                            return Some(path);
+                        }
+                    }
+                } else if !self.walkable[idx]
+                    && self.inside(c)
+                    && !self.locked[idx]
+                    && !carved.contains_key(&idx)
+                {
+                    for (k, &s) in carve_with.iter().enumerate() {
+                        let next = node(idx, k);
+                        if !parent.contains_key(&next)
+                            && allowed(rules, sprite, dir, s)
+                            && self.fits(rules, c, s, at, &carved)
+                        {
+                            parent.insert(next, from);
+                            queue.push_back((c, s, Some(next)));
+                        }
+                    }
+                }
+            }
+        }
+
+        None
+    }
+
+    /// The (cell, sprite) pairs carved on the way to `last`, from the last one back.
+    fn trail(
+        &self,
+        last: Option<usize>,
+        parent: &HashMap<usize, Option<usize>>,
+        carve_with: &[usize],
+    ) -> Vec<(usize, usize)> {
+        let mut path = vec![];
+        let mut node = last;
+        while let Some(id) = node {
+            path.push((id / carve_with.len(), carve_with[id % carve_with.len()]));
+            node = parent[&id];
+        }
+        path
+    }
+
+    fn path(
+        &self,
+        last: Option<usize>,
+        parent: &HashMap<usize, Option<usize>>,
+        carve_with: &[usize],
+    ) -> Option<Vec<(usize, usize)>> {
+        let path = self.trail(last, parent, carve_with);
+
+        // a cell crossed twice would need two different sprites
+        let cells: HashSet<usize> = path.iter().map(|(idx, _)| *idx).collect();
+        if cells.len() == path.len() {
+            Some(path)
+        } else {
+            None
+        }
+    }
+
+    /// Fills a region with void, along with the walls around it that no reachable cell needs.
+    /// A wall only goes when void agrees with every neighbour that stays, otherwise it is left
+    /// as it was.
+    fn remove(
+        &mut self,
+        region: &[usize],
+        regions: &[Option<usize>],
+        main: Option<usize>,
+        rules: &[Rule],
+        pairs: &crate::assets::Pairs,
+    ) {
+        let void = pairs.void();
+        let region: HashSet<usize> = region
+            .iter()
+            .copied()
+            .filter(|idx| !self.locked[*idx])
+            .collect();
+        let mut walls = HashSet::new();
+        for &idx in &region {
+            for c in self.around(self.coord(idx)) {
+                let near_main = self.around(c).any(|n| regions[self.idx(n)] == main);
+                let at = self.idx(c);
+                if !self.walkable[at] && self.inside(c) && !near_main && !self.locked[at] {
+                    walls.insert(at);
+                }
+            }
+        }
+
+        // keeping a wall can make void disagree with it, so settle until nothing else is kept
+        if let Some(void) = void {
+            loop {
+                let kept: Vec<usize> = walls
+                    .iter()
+                    .copied()
+                    .filter(|&idx| {
+                        self.neighbours(self.coord(idx)).any(|(dir, c)| {
+                            let n = self.idx(c);
+                            !walls.contains(&n)
+                                && !region.contains(&n)
+                                && self.sprites[n].map_or(false, |s| !allowed(rules, void, dir, s))
+                        })
+                    })
+                    .collect();
+                if kept.is_empty() {
+                    break;
+                }
+                for idx in kept {
+                    walls.remove(&idx);
+                }
+            }
+        }
+
+        for idx in region.into_iter().chain(walls) {
+            log::debug!("Removing unreachable {:?}", self.coord(idx));
+            self.set(idx, void, pairs);
+        }
+    }
+}
+
+/// Joins every walkable region the start can't reach to the one it can, through the
+/// shortest corridor the adjacency rules allow. Regions that can't be joined are removed.
+/// Returns how many regions were joined and how many removed, or `None` when a vault
+/// can't be reached since it can't be removed.
+fn connect(
+    walls: &mut TileMap<RoomTile>,
+    pairs: &crate::assets::Pairs,
+    rules: &[Rule],
+    start: Coord,
+    locked: &[Coord],
+) -> Option<(usize, usize)> {
+    let mut layout = Layout::read(walls, locked);
+    let carve_with: Vec<usize> = (0..rules.len()).filter(|t| pairs.walkable(*t)).collect();
+    let (mut joined, mut removed) = (0, 0);
+
+    if !layout.in_bounds(start) {
+        return Some((joined, removed));
+    }
+
+    loop {
+        let regions = layout.regions();
+        let main = regions[layout.idx(start)];
+        if main.is_none() {
+            break;
+        }
+
+        let orphan = match regions.iter().find(|r| r.is_some() && **r != main) {
+            Some(orphan) => *orphan,
+            None => break,
+        };
+        let region: Vec<usize> = (0..regions.len())
+            .filter(|idx| regions[*idx] == orphan)
+            .collect();
+
+        match layout.shortest_carve(&region, &regions, main, &carve_with, rules) {
+            Some(path) => {
+                for (idx, sprite) in path {
+                    layout.set(idx, Some(sprite), pairs);
+                }
+                joined += 1;
+            }
+            None if region.iter().any(|idx| layout.locked[*idx]) => return None,
+            None => {
+                layout.remove(&region, &regions, main, rules, pairs);
+                removed += 1;
+            }
+        }
+    }
+
+    layout.write(walls, pairs);
+    Some((joined, removed))
+}
+
+#[allow(clippy::many_single_char_names)]
+pub fn gen_map<R: Rng>(
+    walls: &mut TileMap<RoomTile>,
+    floor: &mut TileMap<FloorTile>,
+    pairs: &crate::assets::Pairs,
+    model: &Model,
+    start: Coord,
+    exit: Coord,
+    vaults: &[PlacedVault],
+    budget: Budget,
+    progress: &Progress,
+    rng: &mut R,
+) -> Result<GenStats, GenError> {
+    let rules = gen_rules(&pairs);
+    let patterns = Patterns::new(model, &rules, pairs);
+    let (open, blocked) = patterns.by_walkable(pairs);
+    let fixed: Vec<(Coord, usize)> = vaults
+        .iter()
+        .flat_map(|v| v.fixed())
+        .filter(|(_, t)| patterns.has(*t))
+        .collect();
+    let locked: Vec<Coord> = fixed.iter().map(|(c, _)| *c).collect();
+    let mut context = wfc::Context::new();
+    let (width, height) = (walls.dimensions().x, walls.dimensions().y);
+    let stats = wfc::GlobalStats::new(patterns.table());
+
+    let started = Instant::now();
+    let mut error = GenError::default();
+    let mut placed = HashSet::new();
+
+    while error.attempts < budget.max_attempts && started.elapsed() < budget.time_limit {
+        error.attempts += 1;
+        progress.0.store(error.attempts, Ordering::Relaxed);
+        let mut wave = wfc::Wave::new(wfc::Size::try_new(width, height).unwrap());
+        let contradiction = Cell::new(false);
+        let path = corridor(start, exit, rng);
+
+        let mut wfc_run = wfc::RunBorrow::new_wrap_forbid(
+            &mut context,
+            &mut wave,
+            &stats,
+            wfc::wrap::WrapNone,
+            ForbidCorner {
+                width: width as i32,
+                height: height as i32,
+                start,
+                sprites: &patterns.sprites,
+                border_tile: pairs.border().filter(|t| patterns.has(*t)),
+                start_tile: pairs.start().filter(|t| patterns.has(*t)),
+                fixed: &fixed,
+                corridor: path,
+                solid: vec![],
+                blocked: &blocked,
+                open: &open,
+                contradiction: &contradiction,
+            },
+            rng,
+        );
+
+        // a single try per attempt so the budget is checked between every collapse
+        if wfc_run
+            .collapse_retrying(wfc::retry::NumTimes(0), rng)
+            .is_err()
+            || contradiction.get()
+        {
+            error.contradictions += 1;
+            continue;
+        }
+
+        wave.grid().map_ref_with_coord(|c, cell| {
+            if let Some(tile) = walls.get_mut(&Point3::new(c.x as u32, c.y as u32, 0)) {
+                let sprite = cell
+                    .chosen_pattern_id()
+                    .ok()
+                    .map(|p| patterns.sprites[p as usize]);
+                let def = sprite.map(|s| pairs.tile(s)).unwrap_or_default();
+                placed.extend(sprite);
+
+                *tile = RoomTile {
+                    sprite,
+                    walkable: def.walkable,
+                    opaque: def.opaque,
+                    ..Default::default()
+                };
+            }
+        });
+
+        let (joined, removed) = match connect(walls, pairs, &rules, start, &locked) {
+            Some(connected) => connected,
+            None => {
+                log::info!("A vault can't be reached, retrying");
+                continue;
+            }
+        };
+        log::info!("Joined {} regions, removed {}", joined, removed);
+
+        let size = reachable(walls, floor, pairs, start);
+        let ratio = size as f32 / (width * height) as f32;
+        log::info!("{} walkable tiles, {}% walkable", size, ratio * 100.);
+
+        error.best_walkable_ratio = error.best_walkable_ratio.max(ratio);
+        if ratio < budget.min_walkable_ratio {
+            continue;
+        }
+
+        let metrics = crate::metrics::measure(walls, start);
+        let missed = budget.thresholds.missed(&metrics);
+        if !missed.is_empty() {
+            log::info!("Map has {}, missed {:?}, retrying", metrics, missed);
+            error.rejected += 1;
+            continue;
+        }
+
+        return Ok(GenStats {
+            attempts: error.attempts,
+            contradictions: error.contradictions,
+            walkable_ratio: ratio,
+            joined_regions: joined,
+            removed_regions: removed,
+            metrics,
+            elapsed: started.elapsed(),
+        });
+    }
+
+    error.unplaced = (0..pairs.max_tiles())
+        .filter(|t| pairs.tiles.contains_key(t) && patterns.has(*t) && !placed.contains(t))
+        .collect();
+    error.elapsed = started.elapsed();
+    Err(error)
+}
+
+/// The cells of a room inside its walls.
+#[derive(Clone, Copy, Debug)]
+struct Rect {
+    x: i32,
+    y: i32,
+    width: i32,
+    height: i32,
+}
+
+impl Rect {
+    fn center(&self) -> Coord {
+        Coord::new(self.x + self.width / 2, self.y + self.height / 2)
+    }
+
+    fn contains(&self, c: Coord) -> bool {
+        c.x >= self.x && c.y >= self.y && c.x < self.x + self.width && c.y < self.y + self.height
+    }
+
+    /// The rectangle with a ring of `by` cells around it.
+    fn grow(&self, by: i32) -> Rect {
+        Rect {
+            x: self.x - by,
+            y: self.y - by,
+            width: self.width + 2 * by,
+            height: self.height + 2 * by,
+        }
+    }
+
+    fn cells(self) -> impl Iterator<Item = Coord> {
+        (self.y..self.y + self.height)
+            .flat_map(move |y| (self.x..self.x + self.width).map(move |x| Coord::new(x, y)))
+    }
+}
+
+/// Rooms and the corridors between them, before any sprite is picked.
+#[derive(Default)]
+struct Plan {
+    rooms: Vec<Rect>,
+    corridors: HashSet<Coord>,
+}
+
+impl Plan {
+    /// Splits `area` along its longer side until the parts are at most `max_size` across, and
+    /// puts a room of at least `min_size` with its walls in each. The rooms on either side of
+    /// a split are joined by a corridor. Returns the center of one of the rooms in `area`.
+    fn split<R: Rng>(&mut self, area: Rect, min_size: i32, max_size: i32, rng: &mut R) -> Coord {
+        let wide = area.width >= area.height;
+        let side = if wide { area.width } else { area.height };
+
+        if side > max_size + 2 && side >= 2 * (min_size + 2) {
+            let at = rng.gen_range(min_size + 2, side - min_size - 1);
+            let (a, b) = if wide {
+                let a = Rect { width: at, ..area };
+                (
+                    a,
+                    Rect {
+                        x: area.x + at,
+                        width: area.width - at,
+                        ..area
+                    },
+                )
+            } else {
+                let a = Rect { height: at, ..area };
+                (
+                    a,
+                    Rect {
+                        y: area.y + at,
+                        height: area.height - at,
+                        ..area
+                    },
+                )
+            };
+
+            let from = self.split(a, min_size, max_size, rng);
+            let to = self.split(b, min_size, max_size, rng);
+            self.corridors.extend(corridor(from, to, rng));
+            return if rng.gen() { from } else { to };
+        }
+
+        let mut size = |space: i32| {
+            let most = max_size.min(space - 2).max(1);
+            let size = rng.gen_range(min_size.min(most), most + 1);
+            (size, 1 + rng.gen_range(0, (space - 2 - size).max(0) + 1))
+        };
+        let (width, x) = size(area.width);
+        let (height, y) = size(area.height);
+        let room = Rect {
+            x: area.x + x,
+            y: area.y + y,
+            width,
+            height,
+        };
+
+        self.rooms.push(room);
+        room.center()
+    }
+
+    fn in_room(&self, c: Coord) -> bool {
+        self.rooms.iter().any(|r| r.contains(c))
+    }
+}
+
+/// Collapses a `width` by `height` wave once, the sprite of every cell row by row.
+#[allow(clippy::too_many_arguments)]
+fn collapse<F: ForbidPattern, R: Rng>(
+    context: &mut wfc::Context,
+    stats: &wfc::GlobalStats,
+    width: u32,
+    height: u32,
+    forbid: F,
+    contradiction: &Cell<bool>,
+    sprites: &[usize],
+    rng: &mut R,
+) -> Option<Vec<usize>> {
+    let mut wave = wfc::Wave::new(wfc::Size::try_new(width, height).ok()?);
+    let mut wfc_run = wfc::RunBorrow::new_wrap_forbid(
+        context,
+        &mut wave,
+        stats,
+        wfc::wrap::WrapNone,
+        forbid,
+        rng,
+    );
+
+    if wfc_run
+        .collapse_retrying(wfc::retry::NumTimes(0), rng)
+        .is_err()
+        || contradiction.get()
+    {
+        return None;
+    }
+
+    wave.grid()
+        .iter()
+        .map(|cell| cell.chosen_pattern_id().ok().map(|p| sprites[p as usize]))
+        .collect()
+}
+
+/// Lays the level out as rooms split out of the map with binary space partitioning, joined by
+/// corridors, instead of leaving rooms to chance. Wfc fills in each room on its own, with the
+/// walls around it kept solid apart from where corridors come in, and then the rest of the map
+/// around the finished rooms with everything off the plan kept solid. The start and exit are
+/// joined to their nearest room. Vaults aren't placed and the walkable ratio isn't checked,
+/// the plan decides how much of the map is open.
+#[allow(clippy::too_many_arguments)]
+pub fn gen_rooms<R: Rng>(
+    walls: &mut TileMap<RoomTile>,
+    floor: &mut TileMap<FloorTile>,
+    pairs: &crate::assets::Pairs,
+    model: &Model,
+    start: Coord,
+    exit: Coord,
+    (min_size, max_size): (u32, u32),
+    budget: Budget,
+    progress: &Progress,
+    rng: &mut R,
+) -> Result<GenStats, GenError> {
+    let rules = gen_rules(&pairs);
+    let patterns = Patterns::new(model, &rules, pairs);
+    let (open, blocked) = patterns.by_walkable(pairs);
+    let mut context = wfc::Context::new();
+    let (width, height) = (walls.dimensions().x, walls.dimensions().y);
+    let stats = wfc::GlobalStats::new(patterns.table());
+    let min_size = min_size.max(1) as i32;
+    let max_size = (max_size as i32).max(min_size);
+
+    let started = Instant::now();
+    let mut error = GenError::default();
+    let mut placed = HashSet::new();
+
+    'attempts: while error.attempts < budget.max_attempts && started.elapsed() < budget.time_limit {
+        error.attempts += 1;
+        progress.0.store(error.attempts, Ordering::Relaxed);
+
+        let mut plan = Plan::default();
+        let inside = Rect {
+            x: 1,
+            y: 1,
+            width: width as i32 - 2,
+            height: height as i32 - 2,
+        };
+        plan.split(inside, min_size, max_size, rng);
+        for at in &[start, exit] {
+            let distance = |r: &&Rect| (r.center().x - at.x).abs() + (r.center().y - at.y).abs();
+            if let Some(nearest) = plan.rooms.iter().min_by_key(distance).map(Rect::center) {
+                plan.corridors.extend(corridor(*at, nearest, rng));
+            }
+        }
+
+        // each room on its own, inside a ring of wall that only corridors go through
+        let mut fixed = vec![];
+        for room in &plan.rooms {
+            let bounds = room.grow(1);
+            let local = |c: Coord| Coord::new(c.x - bounds.x, c.y - bounds.y);
+            let contradiction = Cell::new(false);
+            let forbid = ForbidCorner {
+                width: bounds.width,
+                height: bounds.height,
+                start: local(start),
+                sprites: &patterns.sprites,
+                border_tile: None,
+                start_tile: pairs
+                    .start()
+                    .filter(|t| room.contains(start) && patterns.has(*t)),
+                fixed: &[],
+                corridor: bounds
+                    .cells()
+                    .filter(|c| plan.corridors.contains(c))
+                    .map(local)
+                    .collect(),
+                solid: bounds
+                    .cells()
+                    .filter(|c| !room.contains(*c) && !plan.corridors.contains(c))
+                    .map(local)
+                    .collect(),
+                blocked: &blocked,
+                open: &open,
+                contradiction: &contradiction,
+            };
+
+            let (w, h) = (bounds.width as u32, bounds.height as u32);
+            match collapse(
+                &mut context,
+                &stats,
+                w,
+                h,
+                forbid,
+                &contradiction,
+                &patterns.sprites,
+                rng,
+            ) {
+                Some(sprites) => fixed.extend(room.cells().map(|c| {
+                    let at = local(c);
+                    (c, sprites[(at.y * bounds.width + at.x) as usize])
+                })),
+                None => {
+                    error.contradictions += 1;
+                    continue 'attempts;
+                }
+            }
+        }
+
+        // then the corridors and walls around the finished rooms
+        let contradiction = Cell::new(false);
+        let everywhere = Rect {
+            x: 0,
+            y: 0,
+            width: width as i32,
+            height: height as i32,
+        };
+        let forbid = ForbidCorner {
+            width: width as i32,
+            height: height as i32,
+            start,
+            sprites: &patterns.sprites,
+            border_tile: pairs.border().filter(|t| patterns.has(*t)),
+            start_tile: pairs.start().filter(|t| patterns.has(*t)),
+            fixed: &fixed,
+            corridor: plan
+                .corridors
+                .iter()
+                .copied()
+                .filter(|c| !plan.in_room(*c))
+                .collect(),
+            solid: everywhere
+                .cells()
+                .filter(|c| !plan.in_room(*c) && !plan.corridors.contains(c))
+                .collect(),
+            blocked: &blocked,
+            open: &open,
+            contradiction: &contradiction,
+        };
+        let sprites = match collapse(
+            &mut context,
+            &stats,
+            width,
+            height,
+            forbid,
+            &contradiction,
+            &patterns.sprites,
+            rng,
+        ) {
+            Some(sprites) => sprites,
+            None => {
+                error.contradictions += 1;
+                continue;
+            }
+        };
+
+        for c in everywhere.cells() {
+            if let Some(tile) = walls.get_mut(&Point3::new(c.x as u32, c.y as u32, 0)) {
+                let sprite = sprites[(c.y * everywhere.width + c.x) as usize];
+                let def = pairs.tile(sprite);
+                placed.insert(sprite);
+
+                *tile = RoomTile {
+                    sprite: Some(sprite),
+                    walkable: def.walkable,
+                    opaque: def.opaque,
+                    ..Default::default()
+                };
+            }
+        }
+
+        // rooms filled in with few ways through them can still leave a corner cut off
+        let (joined, removed) = connect(walls, pairs, &rules, start, &[]).unwrap_or_default();
+        let size = reachable(walls, floor, pairs, start);
+        let ratio = size as f32 / (width * height) as f32;
+        error.best_walkable_ratio = error.best_walkable_ratio.max(ratio);
+        log::info!(
+            "{} rooms, {} walkable tiles, {}% walkable",
+            plan.rooms.len(),
+            size,
+            ratio * 100.
+        );
+
+        let metrics = crate::metrics::measure(walls, start);
+        let missed = budget.thresholds.missed(&metrics);
+        if !missed.is_empty() {
+            log::info!("Map has {}, missed {:?}, retrying", metrics, missed);
+            error.rejected += 1;
+            continue;
+        }
+
+        return Ok(GenStats {
+            attempts: error.attempts,
+            contradictions: error.contradictions,
+            walkable_ratio: ratio,
+            joined_regions: joined,
+            removed_regions: removed,
+            metrics,
+            elapsed: started.elapsed(),
+        });
+    }
+
+    error.unplaced = (0..pairs.max_tiles())
+        .filter(|t| pairs.tiles.contains_key(t) && patterns.has(*t) && !placed.contains(t))
+        .collect();
+    error.elapsed = started.elapsed();
+    Err(error)
+}
+
+/// Generates a `size` square of terrain for `Chunks`, its sprites row by row. `edges` are
+/// sprites already placed around the square, one cell outside it, which it is made to fit.
+/// A walkable cross through the middle joins every chunk to the ones next to it.
+pub fn gen_chunk<R: Rng>(
+    pairs: &crate::assets::Pairs,
+    size: u32,
+    edges: &[(Coord, usize)],
+    budget: Budget,
+    rng: &mut R,
+) -> Result<(Vec<usize>, GenStats), GenError> {
+    let rules = gen_rules(&pairs);
+    let patterns = Patterns::new(&Model::Pairs, &rules, pairs);
+    let (open, blocked) = patterns.by_walkable(pairs);
+    let mut context = wfc::Context::new();
+    let stats = wfc::GlobalStats::new(patterns.table());
+
+    // the wave is one cell bigger on every side, to hold the edges
+    let side = size as i32 + 2;
+    let middle = size as i32 / 2 + 1;
+    let fixed: Vec<(Coord, usize)> = edges
+        .iter()
+        .filter(|(_, s)| patterns.has(*s))
+        .map(|(c, s)| (Coord::new(c.x + 1, c.y + 1), *s))
+        .collect();
+    let cross: Vec<Coord> = (0..side)
+        .flat_map(|i| vec![Coord::new(i, middle), Coord::new(middle, i)])
+        .collect();
+
+    let started = Instant::now();
+    let mut error = GenError::default();
+
+    while error.attempts < budget.max_attempts && started.elapsed() < budget.time_limit {
+        error.attempts += 1;
+        let contradiction = Cell::new(false);
+        let forbid = ForbidCorner {
+            width: side,
+            height: side,
+            start: Coord::new(middle, middle),
+            sprites: &patterns.sprites,
+            border_tile: None,
+            start_tile: None,
+            fixed: &fixed,
+            corridor: cross.clone(),
+            solid: vec![],
+            blocked: &blocked,
+            open: &open,
+            contradiction: &contradiction,
+        };
+
+        let sprites = match collapse(
+            &mut context,
+            &stats,
+            side as u32,
+            side as u32,
+            forbid,
+            &contradiction,
+            &patterns.sprites,
+            rng,
+        ) {
+            Some(sprites) => sprites,
+            None => {
+                error.contradictions += 1;
+                continue;
+            }
+        };
+
+        let inner: Vec<usize> = (1..=size as i32)
+            .flat_map(|y| (1..=size as i32).map(move |x| (y * side + x) as usize))
+            .map(|idx| sprites[idx])
+            .collect();
+        let walkable = inner.iter().filter(|s| pairs.walkable(**s)).count();
+
+        return Ok((
+            inner,
+            GenStats {
+                attempts: error.attempts,
+                contradictions: error.contradictions,
+                walkable_ratio: walkable as f32 / (size * size) as f32,
+                elapsed: started.elapsed(),
+                ..Default::default()
+            },
+        ));
+    }
+
+    error.elapsed = started.elapsed();
+    Err(error)
+}
+
+/// Counts the tiles reachable from `start`, and lays a plain floor under the whole map.
+fn reachable(
+    walls: &mut TileMap<RoomTile>,
+    floor: &mut TileMap<FloorTile>,
+    pairs: &crate::assets::Pairs,
+    start: Coord,
+) -> usize {
+    let (width, height) = (walls.dimensions().x, walls.dimensions().y);
+    let mut size = 0;
+    let my_map = SanityMap(walls, crate::map::Moves::Cardinal);
+    let player_idx = my_map.point2d_to_index(Point::new(start.x, start.y));
+    let dijkstra = DijkstraMap::new(width, height, &[player_idx], &my_map, 1000.);
+
+    for x in 0..width {
+        for y in 0..height {
+            let p = Point::new(x, y);
+            if dijkstra.map[my_map.point2d_to_index(p)] < std::f32::MAX {
+                // this tile is reachable
+                size += 1;
+            }
+
+            if let Some(floor_tile) = floor.get_mut(&Point3::new(x, y, 0)) {
+                floor_tile.visited = false;
+                floor_tile.visible = false;
+                floor_tile.tint = None;
+                floor_tile.sprite = pairs.floor();
+            }
+        }
+    }
+
+    size
+}
+
+/// Decorates the floor of a level `gen_map` has built, with the separate `decor` pairs.
+/// Decor on the floor layer is drawn under the walls. Decor on the wall layer takes the place
+/// of the plain walkable tile, along with whether it can be walked on and seen through, and
+/// sits in front of the floor, which is left plain under it.
+/// Tiles tagged `Decoration` only go under walkable cells. When every attempt fails the floor
+/// stays plain.
+pub fn gen_floor<R: Rng>(
+    walls: &mut TileMap<RoomTile>,
+    floor: &mut TileMap<FloorTile>,
+    pairs: &crate::assets::Pairs,
+    decor: &crate::assets::Pairs,
+    budget: Budget,
+    rng: &mut R,
+) -> Result<GenStats, GenError> {
+    let mut rules = gen_rules(decor);
+    let num_patterns = rules.len();
+
+    // sprites the decor pairs never mention are left out, rather than filling a whole level
+    let paired: HashSet<usize> = decor
+        .ns
+        .iter()
+        .chain(decor.we.iter())
+        .flat_map(|&(a, b)| vec![a, b])
+        .collect();
+    for (t, rule) in rules.iter_mut().enumerate() {
+        if !paired.contains(&t) {
+            rule.weight = None;
+        }
+    }
+    let mut context = wfc::Context::new();
+    let (width, height) = (walls.dimensions().x, walls.dimensions().y);
+    let stats = wfc::GlobalStats::new(PatternTable::from_vec(gen_patterns(&rules)));
+
+    let patterns = |keep: &dyn Fn(&crate::assets::TileDef) -> bool| -> Vec<PatternId> {
+        (0..num_patterns)
+            .filter(|t| keep(&decor.tile(*t)))
+            .map(|t| t as PatternId)
+            .collect()
+    };
+    let in_front = patterns(&|t| t.layer == RenderLayer::Wall);
+    let decorations = patterns(&|t| t.layer == RenderLayer::Wall || t.has_tag(TileTag::Decoration));
+
+    let mut cells = vec![];
+    for x in 0..width {
+        for y in 0..height {
+            let tile = tile_at(walls, x, y);
+            let forbidden = if !tile.walkable {
+                &decorations
+            } else if tile.sprite != pairs.start() {
+                &in_front
+            } else {
+                continue;
+            };
+            cells.push((Coord::new(x as i32, y as i32), forbidden.clone()));
+        }
+    }
+
+    let started = Instant::now();
+    let mut error = GenError::default();
+
+    while error.attempts < budget.max_attempts && started.elapsed() < budget.time_limit {
+        error.attempts += 1;
+        let mut wave = wfc::Wave::new(wfc::Size::try_new(width, height).unwrap());
+        let contradiction = Cell::new(false);
+
+        let mut wfc_run = wfc::RunBorrow::new_wrap_forbid(
+            &mut context,
+            &mut wave,
+            &stats,
+            wfc::wrap::WrapNone,
+            ForbidDecor {
+                cells: &cells,
+                contradiction: &contradiction,
+            },
+            rng,
+        );
+
+        if wfc_run
+            .collapse_retrying(wfc::retry::NumTimes(0), rng)
+            .is_err()
+            || contradiction.get()
+        {
+            error.contradictions += 1;
+            continue;
+        }
+
+        wave.grid().map_ref_with_coord(|c, cell| {
+            let at = Point3::new(c.x as u32, c.y as u32, 0);
+            let sprite = match cell.chosen_pattern_id() {
+                Ok(pattern) => pattern as usize,
+                Err(_) => return,
+            };
+
+            match decor.tile(sprite).layer {
+                RenderLayer::Floor => {
+                    if let Some(tile) = floor.get_mut(&at) {
+                        tile.sprite = Some(sprite);
+                    }
+                }
+                RenderLayer::Wall => {
+                    if let Some(tile) = walls.get_mut(&at) {
+                        let def = decor.tile(sprite);
+                        tile.sprite = Some(sprite);
+                        tile.walkable = def.walkable;
+                        tile.opaque = def.opaque;
+                    }
+                    if let Some(tile) = floor.get_mut(&at) {
+                        tile.sprite = decor.floor().or_else(|| pairs.floor());
+                    }
+                }
+            }
+        });
+
+        return Ok(GenStats {
+            attempts: error.attempts,
+            contradictions: error.contradictions,
+            elapsed: started.elapsed(),
+            ..Default::default()
+        });
+    }
+
+    error.elapsed = started.elapsed();
+    Err(error)
+}
+
+fn tile_at<T: amethyst::tiles::Tile>(map: &TileMap<T>, x: u32, y: u32) -> T {
+    map.get(&Point3::new(x, y, 0)).cloned().unwrap_or_default()
+}
+
+/// A plain walled room that is always valid, used when generation gives up.
+pub fn fallback_map(
+    walls: &mut TileMap<RoomTile>,
+    floor: &mut TileMap<FloorTile>,
+    pairs: &crate::assets::Pairs,
+) {
+    let (width, height) = (walls.dimensions().x, walls.dimensions().y);
+
+    for x in 0..width {
+        for y in 0..height {
+            let edge = x == 0 || y == 0 || x == width - 1 || y == height - 1;
+            let sprite = if edge { pairs.border() } else { pairs.start() };
+
+            if let Some(tile) = walls.get_mut(&Point3::new(x, y, 0)) {
+                *tile = RoomTile {
+                    sprite,
+                    walkable: !edge,
+                    opaque: edge,
+                    ..Default::default()
+                };
+            }
+
+            if let Some(floor_tile) = floor.get_mut(&Point3::new(x, y, 0)) {
+                *floor_tile = FloorTile {
+                    sprite: pairs.floor(),
+                    ..Default::default()
+                };
+            }
+        }
+    }
+}
+
+/// Links every storey of a map to the one below with up to `count` ladders, lifts and holes, on
+/// cells that can be walked on on both storeys and away from `keep_clear`. The first link of
+/// each storey is a ladder so there is always a way back up, it goes at `fallback` when no cell
+/// will do. Holes are cut through the walls and the floor so the storey below shows through.
+pub fn link_storeys<R: Rng>(
+    walls: &mut TileMap<RoomTile>,
+    floor: &mut TileMap<FloorTile>,
+    pairs: &crate::assets::Pairs,
+    keep_clear: &[Coord],
+    fallback: Coord,
+    count: usize,
+    rng: &mut R,
+) -> Vec<(Point3<u32>, Link)> {
+    let dim = *walls.dimensions();
+    let walkable = |walls: &TileMap<RoomTile>, c: Coord, z: u32| {
+        walls
+            .get(&Point3::new(c.x as u32, c.y as u32, z))
+            .map_or(false, |t| t.walkable)
+    };
+
+    let mut links = vec![];
+    for z in 1..dim.z {
+        let mut cells: Vec<Coord> = (0..dim.y as i32)
+            .flat_map(|y| (0..dim.x as i32).map(move |x| Coord::new(x, y)))
+            .filter(|c| {
+                !keep_clear.contains(c) && walkable(walls, *c, z) && walkable(walls, *c, z - 1)
+            })
+            .collect();
+        cells.shuffle(rng);
+
+        // spread out so the storeys are linked in more than one place
+        let mut picked: Vec<Coord> = vec![];
+        for c in cells {
+            if picked.len() == count {
+                break;
+            }
+            if picked
+                .iter()
+                .all(|p| (p.x - c.x).abs() + (p.y - c.y).abs() >= 6)
+            {
+                picked.push(c);
+            }
+        }
+        if picked.is_empty() {
+            picked.push(fallback);
+        }
+
+        for (i, c) in picked.into_iter().enumerate() {
+            let link = if i == 0 {
+                Link::Ladder
+            } else {
+                *[Link::Ladder, Link::Lift, Link::Hole].choose(rng).unwrap()
+            };
+            let at = Point3::new(c.x as u32, c.y as u32, z);
+            if let Some(tile) = walls.get_mut(&at) {
+                tile.walkable = true;
+                tile.opaque = false;
+                tile.link = Some(link);
+                tile.sprite = match link {
+                    Link::Hole => None,
+                    Link::Ladder | Link::Lift => pairs.stairs_up(),
+                };
+            }
+            if link == Link::Hole {
+                if let Some(tile) = floor.get_mut(&at) {
+                    tile.sprite = None;
+                }
+            }
+            if let Some(below) = walls.get_mut(&Point3::new(at.x, at.y, z - 1)) {
+                below.walkable = true;
+                below.opaque = false;
+            }
+            links.push((at, link));
+        }
+    }
+
+    links
+}
+
+/// Turns up to `count` walls into secret doors. A wall qualifies when it is one tile thick
+/// between two regions that don't already touch, so every door is a short cut. Returns where
+/// they were put.
+pub fn place_secret_doors<R: Rng>(
+    walls: &mut TileMap<RoomTile>,
+    pairs: &crate::assets::Pairs,
+    count: usize,
+    rng: &mut R,
+) -> Vec<Coord> {
+    let door = match pairs.secret_door() {
+        Some(door) => door,
+        None => return vec![],
+    };
+    let regions = crate::regions::Regions::new(walls);
+    let (width, height) = (walls.dimensions().x as i32, walls.dimensions().y as i32);
+    let walkable = |c: Coord| tile_at(walls, c.x as u32, c.y as u32).walkable;
+
+    let mut candidates: Vec<Coord> = (1..height - 1)
+        .flat_map(|y| (1..width - 1).map(move |x| Coord::new(x, y)))
+        .filter(|c| !walkable(*c))
+        .filter(|c| {
+            [
+                (Coord::new(-1, 0), Coord::new(1, 0)),
+                (Coord::new(0, -1), Coord::new(0, 1)),
+            ]
+            .iter()
+            .any(|(a, b)| {
+                let (a, b) = (*c + *a, *c + *b);
+                if !walkable(a) || !walkable(b) {
+                    return false;
+                }
+                match (regions.region(a), regions.region(b)) {
+                    (Some(a), Some(b)) => a.id != b.id && !a.neighbours.contains(&b.id),
+                    _ => false,
+                }
+            })
+        })
+        .collect();
+    candidates.shuffle(rng);
+
+    let mut placed: Vec<Coord> = vec![];
+    for c in candidates {
+        if placed.len() == count {
+            break;
+        }
+        if placed
+            .iter()
+            .all(|p| (p.x - c.x).abs() + (p.y - c.y).abs() >= 4)
+        {
+            placed.push(c);
+        }
+    }
+
+    for c in &placed {
+        if let Some(tile) = walls.get_mut(&Point3::new(c.x as u32, c.y as u32, 0)) {
+            tile.secret = Some(door);
+        }
+    }
+
+    placed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assets::{Pairs, TileDef, TileTag};
+    use amethyst::core::math::Vector3;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    const FLOOR: usize = 0;
+    const WALL: usize = 1;
+    const VOID: usize = 2;
+    const OTHER: usize = 3; // a pillar or a rug, depending on the test
+
+    /// Pairs where every tile may sit next to every other, except the `banned` ones.
+    fn pairs(other_walkable: bool, banned: &[(usize, usize)]) -> Pairs {
+        let mut pairs = Pairs::default();
+        for (idx, walkable, tag) in [
+            (FLOOR, true, TileTag::Floor),
+            (WALL, false, TileTag::Wall),
+            (VOID, false, TileTag::Void),
+            (OTHER, other_walkable, TileTag::Decoration),
+        ]
+        .iter()
+        {
+            pairs.tiles.insert(
+                *idx,
+                TileDef {
+                    walkable: *walkable,
+                    tags: vec![*tag],
+                    ..Default::default()
+                },
+            );
+        }
+        for a in 0..4 {
+            for b in 0..4 {
+                if !banned.contains(&(a, b)) && !banned.contains(&(b, a)) {
+                    pairs.ns.push((a, b));
+                    pairs.we.push((a, b));
+                }
+            }
+        }
+        pairs
+    }
+
+    /// `.` floor, `#` wall, `o` the other tile, anything else void.
+    fn walls(rows: &[&str], pairs: &Pairs) -> TileMap<RoomTile> {
+        let size = Vector3::new(rows[0].len() as u32, rows.len() as u32, 1);
+        let mut walls = TileMap::<RoomTile>::new(size, Vector3::new(32, 32, 1), None);
+        for (y, row) in rows.iter().enumerate() {
+            for (x, ch) in row.chars().enumerate() {
+                let sprite = match ch {
+                    '.' => FLOOR,
+                    '#' => WALL,
+                    'o' => OTHER,
+                    _ => VOID,
+                };
+                let tile = walls.get_mut(&Point3::new(x as u32, y as u32, 0)).unwrap();
+                tile.sprite = Some(sprite);
+                tile.walkable = pairs.walkable(sprite);
+                tile.opaque = pairs.opaque(sprite);
+            }
+        }
+        walls
+    }
+
+    fn sprite(walls: &TileMap<RoomTile>, x: u32, y: u32) -> Option<usize> {
+        walls.get(&Point3::new(x, y, 0)).unwrap().sprite
+    }
+
+    fn regions(walls: &TileMap<RoomTile>) -> usize {
+        let regions = Layout::read(walls, &[]).regions();
+        regions.iter().flatten().max().map_or(0, |r| r + 1)
+    }
+
+    #[test]
+    fn connect_carves_through_a_wall() {
+        let pairs = pairs(false, &[]);
+        let rules = gen_rules(&pairs);
+        let mut walls = walls(&["#######", "#..#..#", "#..#..#", "#######"], &pairs);
+
+        let outcome = connect(&mut walls, &pairs, &rules, Coord::new(1, 1), &[]);
+
+        assert_eq!(outcome, Some((1, 0)));
+        assert_eq!(regions(&walls), 1);
+    }
+
+    #[test]
+    fn connect_removes_what_it_cannot_reach() {
+        let pairs = pairs(false, &[]);
+        let rules = gen_rules(&pairs);
+        let mut walls = walls(&["#######", "#..#..#", "#..#..#", "#######"], &pairs);
+        // a vault wall in the way can't be carved
+        let locked: Vec<Coord> = (0..4).map(|y| Coord::new(3, y)).collect();
+
+        let outcome = connect(&mut walls, &pairs, &rules, Coord::new(1, 1), &locked);
+
+        assert_eq!(outcome, Some((0, 1)));
+        assert_eq!(regions(&walls), 1);
+        assert_eq!(sprite(&walls, 4, 1), Some(VOID));
+        assert_eq!(sprite(&walls, 3, 1), Some(WALL));
+        assert_eq!(sprite(&walls, 1, 1), Some(FLOOR));
+    }
+
+    #[test]
+    fn connect_gives_up_on_an_unreachable_vault() {
+        let pairs = pairs(false, &[]);
+        let rules = gen_rules(&pairs);
+        let mut walls = walls(&["#######", "#..#..#", "#..#..#", "#######"], &pairs);
+        let locked: Vec<Coord> = (0..4)
+            .map(|y| Coord::new(3, y))
+            .chain(Some(Coord::new(5, 1)))
+            .collect();
+
+        assert_eq!(
+            connect(&mut walls, &pairs, &rules, Coord::new(1, 1), &locked),
+            None
+        );
+    }
+
+    #[test]
+    fn remove_keeps_walls_void_cannot_sit_next_to() {
+        // void may not touch the pillar
+        let pairs = pairs(false, &[(VOID, OTHER)]);
+        let rules = gen_rules(&pairs);
+        let walls = walls(
+            &[
+                "#########",
+                "#.#######",
+                "#.##.####",
+                "#.#######",
+                "#.##o####",
+                "#########",
+            ],
+            &pairs,
+        );
+        let mut layout = Layout::read(&walls, &[]);
+        let regions = layout.regions();
+        let main = regions[layout.idx(Coord::new(1, 1))];
+        let orphan = vec![layout.idx(Coord::new(4, 2))];
+
+        layout.remove(&orphan, &regions, main, &rules, &pairs);
+
+        let at = |x, y| layout.sprites[layout.idx(Coord::new(x, y))];
+        assert_eq!(at(4, 2), Some(VOID));
+        assert_eq!(at(3, 2), Some(VOID));
+        assert_eq!(at(5, 1), Some(VOID));
+        assert_eq!(at(4, 3), Some(WALL), "next to the pillar");
+        assert_eq!(at(4, 4), Some(OTHER));
+    }
+
+    #[test]
+    fn shortest_carve_follows_the_rules_round_a_corner() {
+        // floor and rug have to take turns
+        let pairs = pairs(true, &[(FLOOR, FLOOR), (OTHER, OTHER)]);
+        let rules = gen_rules(&pairs);
+        let walls = walls(
+            &[
+                "#######", "#.#####", "#######", "#######", "####..#", "#######",
+            ],
+            &pairs,
+        );
+        let mut layout = Layout::read(&walls, &[]);
+        let regions = layout.regions();
+        let main = regions[layout.idx(Coord::new(4, 4))];
+        let orphan = vec![layout.idx(Coord::new(1, 1))];
+
+        let path = layout
+            .shortest_carve(&orphan, &regions, main, &[FLOOR, OTHER], &rules)
+            .expect("a corridor");
+        assert_eq!(path.len(), 5);
+        for &(idx, sprite) in &path {
+            layout.set(idx, Some(sprite), &pairs);
+        }
+
+        for (idx, sprite) in path {
+            let c = layout.coord(idx);
+            for (dir, n) in layout.neighbours(c) {
+                let next = layout.sprites[layout.idx(n)].unwrap();
+                assert!(allowed(&rules, sprite, dir, next), "{:?} {:?}", c, n);
+            }
+        }
+        assert!(layout.regions().iter().flatten().all(|r| *r == 0));
+    }
+
+    #[test]
+    fn shortest_carve_is_none_when_nothing_fits() {
+        // nothing walkable may touch the floor of the main region
+        let pairs = pairs(true, &[(FLOOR, FLOOR), (FLOOR, OTHER)]);
+        let rules = gen_rules(&pairs);
+        let walls = walls(&["#####", "#.#.#", "#####"], &pairs);
+        let layout = Layout::read(&walls, &[]);
+        let regions = layout.regions();
+        let main = regions[layout.idx(Coord::new(1, 1))];
+        let orphan = vec![layout.idx(Coord::new(3, 1))];
+
+        assert!(layout
+            .shortest_carve(&orphan, &regions, main, &[FLOOR, OTHER], &rules)
+            .is_none());
+    }
+
+    #[test]
+    fn fits_counts_cells_carved_on_the_way() {
+        let pairs = pairs(true, &[(OTHER, OTHER)]);
+        let rules = gen_rules(&pairs);
+        let walls = walls(&["#####", "#####", "#####"], &pairs);
+        let layout = Layout::read(&walls, &[]);
+        let (at, from) = (Coord::new(2, 1), Coord::new(1, 1));
+
+        assert!(layout.fits(&rules, at, OTHER, from, &HashMap::new()));
+        let carved: HashMap<usize, usize> = Some((layout.idx(Coord::new(2, 0)), OTHER))
+            .into_iter()
+            .collect();
+        assert!(!layout.fits(&rules, at, OTHER, from, &carved));
+    }
+
+    #[test]
+    fn wall_layer_decor_decides_walkable_and_opaque() {
+        let pairs = pairs(false, &[]);
+        let mut walls = walls(&["..."], &pairs);
+        let mut floor =
+            TileMap::<FloorTile>::new(Vector3::new(3, 1, 1), Vector3::new(32, 32, 1), None);
+        // low fences, the only sprites the decor pairs allow
+        let mut decor = Pairs::default();
+        for &fence in &[VOID, OTHER] {
+            let def = TileDef {
+                opaque: false,
+                layer: RenderLayer::Wall,
+                ..Default::default()
+            };
+            decor.tiles.insert(fence, def);
+            for &other in &[VOID, OTHER] {
+                decor.ns.push((fence, other));
+                decor.we.push((fence, other));
+            }
+        }
+        let mut rng = StdRng::seed_from_u64(0);
+
+        let outcome = gen_floor(
+            &mut walls,
+            &mut floor,
+            &pairs,
+            &decor,
+            Budget::default(),
+            &mut rng,
+        );
+
+        assert!(outcome.is_ok());
+        for x in 0..3 {
+            let tile = walls.get(&Point3::new(x, 0, 0)).unwrap();
+            assert!(tile.sprite == Some(VOID) || tile.sprite == Some(OTHER));
+            assert!(!tile.walkable);
+            assert!(!tile.opaque);
+        }
+    }
+}
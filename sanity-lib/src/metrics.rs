@@ -0,0 +1,378 @@
+//! Measures the layout of a generated level, and the thresholds a level has to meet to be kept.
+
+use crate::tile::RoomTile;
+use amethyst::{
+    core::math::Point3,
+    tiles::{Map, MapStorage, TileMap},
+};
+use direction::Coord;
+use serde::{Deserialize, Serialize};
+use std::{collections::VecDeque, fmt};
+
+/// Shape of the part of a level that can be walked to from the start.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Metrics {
+    pub walkable: usize,
+    pub dead_ends: usize,         // cells with a single way out
+    pub loops: usize,             // ways around a wall, open 2x2 squares don't count
+    pub chokepoints: usize,       // cells that cut the level in two when blocked
+    pub room_area: usize,         // cells that are part of an open 2x2 square
+    pub corridor_area: usize,     // every other cell
+    pub longest_path: usize,      // steps between the two cells farthest apart
+    pub largest_open_area: usize, // cells in the biggest connected patch of room
+}
+
+impl Metrics {
+    pub fn room_ratio(&self) -> f32 {
+        self.room_area as f32 / self.walkable.max(1) as f32
+    }
+}
+
+impl fmt::Display for Metrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "dead ends {} loops {} chokepoints {} rooms {:.0}% longest path {} largest open area {}",
+            self.dead_ends,
+            self.loops,
+            self.chokepoints,
+            self.room_ratio() * 100.,
+            self.longest_path,
+            self.largest_open_area
+        )
+    }
+}
+
+/// Limits a generated level has to be within, every limit is optional.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Thresholds {
+    pub max_dead_ends: Option<usize>,
+    pub min_loops: Option<usize>,
+    pub max_chokepoints: Option<usize>,
+    pub min_room_ratio: Option<f32>,
+    pub max_room_ratio: Option<f32>,
+    pub min_longest_path: Option<usize>,
+    pub min_largest_open_area: Option<usize>,
+    pub max_largest_open_area: Option<usize>,
+}
+
+impl Thresholds {
+    /// Names of the limits `metrics` is outside of, empty when the level is good.
+    pub fn missed(&self, metrics: &Metrics) -> Vec<&'static str> {
+        let below = |min: Option<usize>, value: usize| min.map_or(false, |min| value < min);
+        let above = |max: Option<usize>, value: usize| max.map_or(false, |max| value > max);
+        let ratio = metrics.room_ratio();
+
+        vec![
+            (
+                "max_dead_ends",
+                above(self.max_dead_ends, metrics.dead_ends),
+            ),
+            ("min_loops", below(self.min_loops, metrics.loops)),
+            (
+                "max_chokepoints",
+                above(self.max_chokepoints, metrics.chokepoints),
+            ),
+            (
+                "min_room_ratio",
+                self.min_room_ratio.map_or(false, |min| ratio < min),
+            ),
+            (
+                "max_room_ratio",
+                self.max_room_ratio.map_or(false, |max| ratio > max),
+            ),
+            (
+                "min_longest_path",
+                below(self.min_longest_path, metrics.longest_path),
+            ),
+            (
+                "min_largest_open_area",
+                below(self.min_largest_open_area, metrics.largest_open_area),
+            ),
+            (
+                "max_largest_open_area",
+                above(self.max_largest_open_area, metrics.largest_open_area),
+            ),
+        ]
+        .into_iter()
+        .filter(|(_, missed)| *missed)
+        .map(|(name, _)| name)
+        .collect()
+    }
+}
+
+/// Offsets in the order north, east, south, west.
+const DIRECTIONS: [(i32, i32); 4] = [(0, -1), (1, 0), (0, 1), (-1, 0)];
+
+/// Corners of a 2x2 square from its top left.
+const SQUARE: [(i32, i32); 4] = [(0, 0), (1, 0), (0, 1), (1, 1)];
+
+pub(crate) struct Grid {
+    pub(crate) width: i32,
+    pub(crate) height: i32,
+    pub(crate) open: Vec<bool>, // cells that can be walked through
+}
+
+impl Grid {
+    /// Every walkable tile of `walls`.
+    pub(crate) fn walkable(walls: &TileMap<RoomTile>) -> Self {
+        let (width, height) = (walls.dimensions().x as i32, walls.dimensions().y as i32);
+        let open = (0..width * height)
+            .map(|idx| {
+                let at = Point3::new((idx % width) as u32, (idx / width) as u32, 0);
+                walls.get(&at).map_or(false, |t| t.walkable)
+            })
+            .collect();
+
+        Grid {
+            width,
+            height,
+            open,
+        }
+    }
+
+    pub(crate) fn idx(&self, c: Coord) -> usize {
+        (c.y * self.width + c.x) as usize
+    }
+
+    pub(crate) fn coord(&self, idx: usize) -> Coord {
+        Coord::new(idx as i32 % self.width, idx as i32 / self.width)
+    }
+
+    pub(crate) fn is_open(&self, c: Coord) -> bool {
+        c.x >= 0 && c.y >= 0 && c.x < self.width && c.y < self.height && self.open[self.idx(c)]
+    }
+
+    pub(crate) fn neighbours(&self, idx: usize) -> impl Iterator<Item = usize> + '_ {
+        let c = self.coord(idx);
+        DIRECTIONS
+            .iter()
+            .map(move |(dx, dy)| Coord::new(c.x + dx, c.y + dy))
+            .filter(move |n| self.is_open(*n))
+            .map(move |n| self.idx(n))
+    }
+
+    /// Steps from `from` to every open cell, `usize::MAX` where it can't get to.
+    pub(crate) fn distances(&self, from: usize) -> Vec<usize> {
+        let mut dist = vec![usize::MAX; self.open.len()];
+        let mut open = VecDeque::new();
+        dist[from] = 0;
+        open.push_back(from);
+
+        while let Some(idx) = open.pop_front() {
+            for n in self.neighbours(idx) {
+                if dist[n] == usize::MAX {
+                    dist[n] = dist[idx] + 1;
+                    open.push_back(n);
+                }
+            }
+        }
+
+        dist
+    }
+
+    /// The top left corner of an open 2x2 square.
+    fn square(&self, c: Coord) -> bool {
+        SQUARE
+            .iter()
+            .all(|(dx, dy)| self.is_open(Coord::new(c.x + dx, c.y + dy)))
+    }
+
+    /// Cells that are part of an open 2x2 square, the rest of the open cells are corridor.
+    pub(crate) fn rooms(&self) -> Vec<bool> {
+        let mut room = vec![false; self.open.len()];
+        for idx in (0..self.open.len()).filter(|i| self.open[*i]) {
+            let c = self.coord(idx);
+            if self.square(c) {
+                for (dx, dy) in &SQUARE {
+                    room[self.idx(Coord::new(c.x + dx, c.y + dy))] = true;
+                }
+            }
+        }
+        room
+    }
+
+    /// Cells that split the open area when removed, found with an iterative Tarjan walk.
+    fn articulation_points(&self, root: usize) -> usize {
+        let n = self.open.len();
+        let (mut order, mut low) = (vec![usize::MAX; n], vec![0; n]);
+        let mut cut = vec![false; n];
+        let mut counter = 0;
+        let mut root_children = 0;
+
+        order[root] = counter;
+        low[root] = counter;
+        let mut stack: Vec<(usize, usize, Vec<usize>)> =
+            vec![(root, usize::MAX, self.neighbours(root).collect())];
+
+        while let Some((idx, parent, mut rest)) = stack.pop() {
+            match rest.pop() {
+                Some(next) => {
+                    stack.push((idx, parent, rest));
+                    if order[next] == usize::MAX {
+                        counter += 1;
+                        order[next] = counter;
+                        low[next] = counter;
+                        if idx == root {
+                            root_children += 1;
+                        }
+                        stack.push((next, idx, self.neighbours(next).collect()));
+                    } else if next != parent {
+                        low[idx] = low[idx].min(order[next]);
+                    }
+                }
+                None => {
+                    if parent != usize::MAX {
+                        low[parent] = low[parent].min(low[idx]);
+                        if parent != root && low[idx] >= order[parent] {
+                            cut[parent] = true;
+                        }
+                    }
+                }
+            }
+        }
+
+        cut[root] = root_children > 1;
+        cut.iter().filter(|c| **c).count()
+    }
+}
+
+/// Measures the walkable area of `walls` reachable from `start`.
+pub fn measure(walls: &TileMap<RoomTile>, start: Coord) -> Metrics {
+    let everything = Grid::walkable(walls);
+    let (width, height) = (everything.width, everything.height);
+    if !everything.is_open(start) {
+        return Metrics::default();
+    }
+
+    let reach = everything.distances(everything.idx(start));
+    let grid = Grid {
+        open: reach.iter().map(|d| *d != usize::MAX).collect(),
+        ..everything
+    };
+    let cells: Vec<usize> = (0..grid.open.len()).filter(|i| grid.open[*i]).collect();
+
+    let mut metrics = Metrics {
+        walkable: cells.len(),
+        ..Default::default()
+    };
+
+    let mut edges = 0;
+    let mut squares = 0;
+    for &idx in &cells {
+        let exits = grid.neighbours(idx).count();
+        edges += exits;
+        if exits == 1 {
+            metrics.dead_ends += 1;
+        }
+        if grid.square(grid.coord(idx)) {
+            squares += 1;
+        }
+    }
+    let room = grid.rooms();
+    // every independent cycle of a connected grid either goes round a 2x2 square or round a wall
+    metrics.loops = (edges / 2 + 1).saturating_sub(cells.len() + squares);
+
+    metrics.room_area = room.iter().filter(|r| **r).count();
+    metrics.corridor_area = metrics.walkable - metrics.room_area;
+    metrics.chokepoints = grid.articulation_points(grid.idx(start));
+
+    // two sweeps, exact when the level is a tree and close otherwise
+    let farthest = |dist: &[usize]| {
+        cells
+            .iter()
+            .copied()
+            .max_by_key(|i| dist[*i])
+            .map(|i| (i, dist[i]))
+            .unwrap_or((0, 0))
+    };
+    let (far, _) = farthest(&reach);
+    metrics.longest_path = farthest(&grid.distances(far)).1;
+
+    let rooms = Grid {
+        width,
+        height,
+        open: room,
+    };
+    let mut seen = vec![false; rooms.open.len()];
+    for idx in 0..rooms.open.len() {
+        if rooms.open[idx] && !seen[idx] {
+            let dist = rooms.distances(idx);
+            let patch: Vec<usize> = (0..dist.len()).filter(|i| dist[*i] != usize::MAX).collect();
+            for i in &patch {
+                seen[*i] = true;
+            }
+            metrics.largest_open_area = metrics.largest_open_area.max(patch.len());
+        }
+    }
+
+    metrics
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use amethyst::core::math::Vector3;
+
+    /// `.` walkable, anything else wall.
+    fn walls(rows: &[&str]) -> TileMap<RoomTile> {
+        let size = Vector3::new(rows[0].len() as u32, rows.len() as u32, 1);
+        let mut walls = TileMap::<RoomTile>::new(size, Vector3::new(32, 32, 1), None);
+        for (y, row) in rows.iter().enumerate() {
+            for (x, ch) in row.chars().enumerate() {
+                let tile = walls.get_mut(&Point3::new(x as u32, y as u32, 0)).unwrap();
+                tile.walkable = ch == '.';
+                tile.opaque = ch != '.';
+            }
+        }
+        walls
+    }
+
+    #[test]
+    fn ring_has_a_loop_and_no_chokepoints() {
+        let ring = walls(&["#####", "#...#", "#.#.#", "#...#", "#####"]);
+        let metrics = measure(&ring, Coord::new(1, 1));
+
+        assert_eq!(metrics.walkable, 8);
+        assert_eq!(metrics.loops, 1);
+        assert_eq!(metrics.dead_ends, 0);
+        assert_eq!(metrics.chokepoints, 0);
+        assert_eq!(metrics.longest_path, 4);
+        assert_eq!(metrics.room_area, 0);
+    }
+
+    #[test]
+    fn corridor_is_all_dead_ends_and_chokepoints() {
+        let corridor = walls(&["#######", "#.....#", "#######"]);
+        let metrics = measure(&corridor, Coord::new(1, 1));
+
+        assert_eq!(metrics.walkable, 5);
+        assert_eq!(metrics.loops, 0);
+        assert_eq!(metrics.dead_ends, 2);
+        assert_eq!(metrics.chokepoints, 3);
+        assert_eq!(metrics.longest_path, 4);
+        assert_eq!(metrics.corridor_area, 5);
+    }
+
+    #[test]
+    fn open_room_has_no_loops() {
+        let room = walls(&["#####", "#...#", "#...#", "#...#", "#####"]);
+        let metrics = measure(&room, Coord::new(2, 2));
+
+        assert_eq!(metrics.walkable, 9);
+        assert_eq!(metrics.loops, 0);
+        assert_eq!(metrics.dead_ends, 0);
+        assert_eq!(metrics.chokepoints, 0);
+        assert_eq!(metrics.longest_path, 4);
+        assert_eq!(metrics.room_area, 9);
+        assert_eq!(metrics.largest_open_area, 9);
+    }
+
+    #[test]
+    fn only_what_the_start_reaches_is_measured() {
+        let split = walls(&["#######", "#..#..#", "#######"]);
+        assert_eq!(measure(&split, Coord::new(1, 1)).walkable, 2);
+        assert_eq!(measure(&split, Coord::new(0, 0)), Metrics::default());
+    }
+}
@@ -0,0 +1,242 @@
+//! Splits the walkable part of a generated level into rooms, corridors and the junctions between them.
+
+use crate::{metrics::Grid, tile::RoomTile};
+use amethyst::tiles::TileMap;
+use direction::Coord;
+use std::collections::{BTreeSet, VecDeque};
+
+/// What a single walkable tile is part of.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TileKind {
+    Room,     // part of an open 2x2 square
+    Corridor, // a way in and a way out
+    DeadEnd,  // a single way out
+    Junction, // three or four ways out
+}
+
+impl TileKind {
+    /// Kind of the region the tile is grouped into, dead ends belong to their corridor.
+    fn region(self) -> TileKind {
+        match self {
+            TileKind::DeadEnd => TileKind::Corridor,
+            kind => kind,
+        }
+    }
+}
+
+/// A connected patch of tiles of the same kind, numbered from 0.
+#[derive(Clone, Debug)]
+pub struct Region {
+    pub id: usize,
+    pub kind: TileKind, // `Room`, `Corridor` or `Junction`
+    pub cells: Vec<Coord>,
+    pub neighbours: BTreeSet<usize>, // regions touching this one
+}
+
+/// Every walkable tile of a level labelled, and grouped into regions.
+#[derive(Clone, Debug, Default)]
+pub struct Regions {
+    width: i32,
+    height: i32,
+    kinds: Vec<Option<TileKind>>,
+    ids: Vec<Option<usize>>,
+    pub regions: Vec<Region>,
+}
+
+impl Regions {
+    pub fn new(walls: &TileMap<RoomTile>) -> Self {
+        let grid = Grid::walkable(walls);
+        let room = grid.rooms();
+        let kinds: Vec<Option<TileKind>> = (0..grid.open.len())
+            .map(|idx| match grid.neighbours(idx).count() {
+                _ if !grid.open[idx] => None,
+                _ if room[idx] => Some(TileKind::Room),
+                0 | 1 => Some(TileKind::DeadEnd),
+                2 => Some(TileKind::Corridor),
+                _ => Some(TileKind::Junction),
+            })
+            .collect();
+
+        let mut ids: Vec<Option<usize>> = vec![None; kinds.len()];
+        let mut regions: Vec<Region> = vec![];
+        for (first, kind) in kinds.iter().enumerate() {
+            let kind = match kind {
+                Some(kind) if ids[first].is_none() => kind.region(),
+                _ => continue,
+            };
+
+            let id = regions.len();
+            let mut cells = vec![];
+            let mut open = VecDeque::new();
+            ids[first] = Some(id);
+            open.push_back(first);
+            while let Some(idx) = open.pop_front() {
+                cells.push(grid.coord(idx));
+                for n in grid.neighbours(idx) {
+                    if ids[n].is_none() && kinds[n].map(TileKind::region) == Some(kind) {
+                        ids[n] = Some(id);
+                        open.push_back(n);
+                    }
+                }
+            }
+
+            regions.push(Region {
+                id,
+                kind,
+                cells,
+                neighbours: BTreeSet::new(),
+            });
+        }
+
+        for (idx, id) in ids.iter().enumerate() {
+            if let Some(id) = *id {
+                for other in grid.neighbours(idx).filter_map(|n| ids[n]) {
+                    if other != id {
+                        regions[id].neighbours.insert(other);
+                    }
+                }
+            }
+        }
+
+        Regions {
+            width: grid.width,
+            height: grid.height,
+            kinds,
+            ids,
+            regions,
+        }
+    }
+
+    fn idx(&self, at: Coord) -> Option<usize> {
+        if at.x >= 0 && at.y >= 0 && at.x < self.width && at.y < self.height {
+            Some((at.y * self.width + at.x) as usize)
+        } else {
+            None
+        }
+    }
+
+    /// Label of the tile at `at`, `None` when it can't be walked on.
+    pub fn kind(&self, at: Coord) -> Option<TileKind> {
+        self.idx(at).and_then(|idx| self.kinds[idx])
+    }
+
+    pub fn region(&self, at: Coord) -> Option<&Region> {
+        self.idx(at)
+            .and_then(|idx| self.ids[idx])
+            .map(|id| &self.regions[id])
+    }
+
+    /// The region at `at` and every region up to `steps` regions away from it.
+    pub fn around(&self, at: Coord, steps: usize) -> BTreeSet<usize> {
+        let mut found = BTreeSet::new();
+        let mut open = VecDeque::new();
+        if let Some(region) = self.region(at) {
+            found.insert(region.id);
+            open.push_back((region.id, 0));
+        }
+
+        while let Some((id, distance)) = open.pop_front() {
+            if distance < steps {
+                for next in &self.regions[id].neighbours {
+                    if found.insert(*next) {
+                        open.push_back((*next, distance + 1));
+                    }
+                }
+            }
+        }
+
+        found
+    }
+
+    /// The room tile closest to `to` that is at least `min_distance` steps, counted along
+    /// the axes, from `away_from`.
+    pub fn nearest_room(&self, to: Coord, away_from: Coord, min_distance: i32) -> Option<Coord> {
+        let distance = |a: Coord, b: Coord| (a.x - b.x).abs() + (a.y - b.y).abs();
+        self.regions
+            .iter()
+            .filter(|r| r.kind == TileKind::Room)
+            .flat_map(|r| r.cells.iter().copied())
+            .filter(|c| distance(*c, away_from) >= min_distance)
+            .min_by_key(|c| distance(*c, to))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use amethyst::{
+        core::math::{Point3, Vector3},
+        tiles::MapStorage,
+    };
+
+    /// `.` walkable, anything else wall.
+    fn walls(rows: &[&str]) -> TileMap<RoomTile> {
+        let size = Vector3::new(rows[0].len() as u32, rows.len() as u32, 1);
+        let mut walls = TileMap::<RoomTile>::new(size, Vector3::new(32, 32, 1), None);
+        for (y, row) in rows.iter().enumerate() {
+            for (x, ch) in row.chars().enumerate() {
+                let tile = walls.get_mut(&Point3::new(x as u32, y as u32, 0)).unwrap();
+                tile.walkable = ch == '.';
+            }
+        }
+        walls
+    }
+
+    /// A room with a dead end below it and a corridor out east that splits in two.
+    fn level() -> Regions {
+        Regions::new(&walls(&[
+            "#########",
+            "#...#####",
+            "#...#####",
+            "#.......#",
+            "#.####.##",
+            "#########",
+        ]))
+    }
+
+    #[test]
+    fn tiles_are_labelled_by_their_ways_out() {
+        let regions = level();
+
+        assert_eq!(regions.kind(Coord::new(2, 2)), Some(TileKind::Room));
+        assert_eq!(regions.kind(Coord::new(3, 3)), Some(TileKind::Room));
+        assert_eq!(regions.kind(Coord::new(4, 3)), Some(TileKind::Corridor));
+        assert_eq!(regions.kind(Coord::new(6, 3)), Some(TileKind::Junction));
+        assert_eq!(regions.kind(Coord::new(7, 3)), Some(TileKind::DeadEnd));
+        assert_eq!(regions.kind(Coord::new(1, 4)), Some(TileKind::DeadEnd));
+        assert_eq!(regions.kind(Coord::new(0, 0)), None);
+    }
+
+    #[test]
+    fn dead_ends_join_their_corridor() {
+        let regions = level();
+
+        let room = regions.region(Coord::new(1, 1)).unwrap();
+        assert_eq!(room.kind, TileKind::Room);
+        assert_eq!(room.cells.len(), 9);
+        let corridor = regions.region(Coord::new(4, 3)).unwrap();
+        assert_eq!(corridor.kind, TileKind::Corridor);
+        assert_eq!(corridor.cells.len(), 2);
+        let junction = regions.region(Coord::new(6, 3)).unwrap();
+        assert_eq!(junction.kind, TileKind::Junction);
+        assert_eq!(junction.neighbours.len(), 3);
+        assert_eq!(
+            regions.region(Coord::new(1, 4)).unwrap().kind,
+            TileKind::Corridor
+        );
+        assert_eq!(regions.around(Coord::new(1, 1), 2).len(), 4);
+    }
+
+    #[test]
+    fn nearest_room_keeps_away_from_the_start() {
+        let regions = level();
+        let (exit, start) = (Coord::new(7, 3), Coord::new(1, 1));
+
+        assert_eq!(regions.nearest_room(exit, start, 0), Some(Coord::new(3, 3)));
+        assert_eq!(
+            regions.nearest_room(start, start, 3),
+            Some(Coord::new(3, 2))
+        );
+        assert_eq!(regions.nearest_room(exit, start, 10), None);
+    }
+}
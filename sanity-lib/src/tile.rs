@@ -1,6 +1,11 @@
 extern crate amethyst;
 
-use amethyst::{core::math::Point3, ecs::World, renderer::palette, tiles::Tile};
+use amethyst::{
+    core::{math::Point3, timing::Time},
+    ecs::World,
+    renderer::palette,
+    tiles::Tile,
+};
 
 type TileSetIndex = usize;
 
@@ -12,32 +17,113 @@ pub struct Candidates {
     pub w: Vec<TileSetIndex>,
 }
 
-#[derive(Clone, Debug, Default)]
+/// How a tile leads down to the storey below, the tile under a ladder or a lift leads back up.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Link {
+    Ladder,
+    Lift,
+    Hole, // one way, whatever steps in falls through
+}
+
+impl Link {
+    /// Whether the storey below can come back up through it.
+    pub fn climbable(self) -> bool {
+        self != Link::Hole
+    }
+}
+
+/// Storey the player is on, only it and the storey under it are drawn.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ViewedStorey(pub u32);
+
+/// How bright the tiles of the storey at `coord` are drawn, `None` when they aren't drawn.
+fn shade(coord: Point3<u32>, world: &World) -> Option<f32> {
+    let viewed = world.try_fetch::<ViewedStorey>().map_or(0, |s| s.0);
+    match coord.z {
+        z if z == viewed => Some(1.),
+        z if z + 1 == viewed => Some(0.4),
+        _ => None,
+    }
+}
+
+fn dim(tint: palette::Srgba, by: f32) -> palette::Srgba {
+    palette::Srgba::new(tint.red * by, tint.green * by, tint.blue * by, tint.alpha)
+}
+
+/// Seconds a secret door flashes for once it is found.
+const REVEAL_TIME: f64 = 0.6;
+
+/// Brightness of a secret door that was found at `revealed`, it flashes and fades back.
+fn flash(revealed: Option<f64>, world: &World) -> f32 {
+    let now = world
+        .try_fetch::<Time>()
+        .map_or(0., |t| t.absolute_time_seconds());
+    match revealed.map(|at| now - at) {
+        Some(t) if t < REVEAL_TIME => 1. + 2. * (1. - t / REVEAL_TIME) as f32,
+        _ => 1.,
+    }
+}
+
+#[derive(Clone, Debug)]
 pub struct RoomTile {
     pub sprite: Option<TileSetIndex>,
     pub visible: bool,
     pub candidates: Candidates,
     pub walkable: bool,
+    pub opaque: bool,
     pub tint: Option<palette::Srgba>,
     pub visited: bool,
+    pub link: Option<Link>,           // way down to the storey below
+    pub secret: Option<TileSetIndex>, // a wall hiding a door with this sprite until it is found
+    pub revealed: Option<f64>,        // when the secret door was found, for its flash
+}
+
+impl Default for RoomTile {
+    fn default() -> Self {
+        // a tile nobody filled in is solid wall, so it blocks sight as well as the way
+        Self {
+            sprite: None,
+            visible: false,
+            candidates: Candidates::default(),
+            walkable: false,
+            opaque: true,
+            tint: None,
+            visited: false,
+            link: None,
+            secret: None,
+            revealed: None,
+        }
+    }
+}
+
+impl RoomTile {
+    /// Opens up the door a secret wall hides, false when it hides nothing.
+    pub fn reveal(&mut self, now: f64) -> bool {
+        match self.secret.take() {
+            Some(door) => {
+                self.sprite = Some(door);
+                self.walkable = true;
+                self.opaque = false;
+                self.revealed = Some(now);
+                true
+            }
+            None => false,
+        }
+    }
 }
 
 impl Tile for RoomTile {
-    fn sprite(&self, _: Point3<u32>, _: &World) -> Option<usize> {
-        // TODO: based on type of sprite and world conditions this sprite could change
-        // e.g. if sanity changes (world) and this is a wall, it could reveal a door!
-        // this could be stored on struct or we can determine it later but we would need to know
-        // how many doors are in the room (which means need ref to room)
-        // also would probably have some animation for change
-        if self.visited {
+    fn sprite(&self, coord: Point3<u32>, world: &World) -> Option<usize> {
+        // a secret door is drawn as the wall it is in until `reveal` swaps the sprite
+        if self.visited && shade(coord, world).is_some() {
             self.sprite
         } else {
             None
         }
     }
 
-    fn tint(&self, _: Point3<u32>, _: &World) -> palette::Srgba {
-        if let Some(tint) = self.tint {
+    fn tint(&self, coord: Point3<u32>, world: &World) -> palette::Srgba {
+        let tint = if let Some(tint) = self.tint {
             tint
         } else {
             if self.visible {
@@ -45,7 +131,11 @@ impl Tile for RoomTile {
             } else {
                 palette::Srgba::new(0.1, 0.1, 0.1, 1.)
             }
-        }
+        };
+        dim(
+            tint,
+            shade(coord, world).unwrap_or(1.) * flash(self.revealed, world),
+        )
     }
 }
 
@@ -58,16 +148,16 @@ pub struct FloorTile {
 }
 
 impl Tile for FloorTile {
-    fn sprite(&self, _: Point3<u32>, _: &World) -> Option<usize> {
-        if self.visited {
+    fn sprite(&self, coord: Point3<u32>, world: &World) -> Option<usize> {
+        if self.visited && shade(coord, world).is_some() {
             self.sprite
         } else {
             None
         }
     }
 
-    fn tint(&self, _: Point3<u32>, _: &World) -> palette::Srgba {
-        if let Some(tint) = self.tint {
+    fn tint(&self, coord: Point3<u32>, world: &World) -> palette::Srgba {
+        let tint = if let Some(tint) = self.tint {
             tint
         } else {
             if self.visible {
@@ -75,6 +165,7 @@ impl Tile for FloorTile {
             } else {
                 palette::Srgba::new(0.1, 0.1, 0.1, 1.)
             }
-        }
+        };
+        dim(tint, shade(coord, world).unwrap_or(1.))
     }
 }
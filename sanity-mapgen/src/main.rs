@@ -0,0 +1,632 @@
+//! Generates levels from a pairs file without starting the game.
+
+use amethyst::{
+    core::math::{Point3, Vector3},
+    tiles::{Map, MapStorage, Tile, TileMap},
+};
+use direction::Coord;
+use image::{imageops, RgbaImage};
+use rand::{rngs::StdRng, SeedableRng};
+use sanity_lib::{
+    assets::{Example, Pairs, TileTag, Vault},
+    chunks::{self, Chunks},
+    mapgen::{self, Budget, GenError, GenStats, Generator, Model, Progress},
+    metrics::{Metrics, Thresholds},
+    regions::Regions,
+    tile::{FloorTile, RoomTile},
+};
+use serde::{Deserialize, Serialize};
+use std::{fs, num::NonZeroU32, path::PathBuf, time::Duration};
+
+const USAGE: &str = "usage: sanity-mapgen <pairs.ron> [--size WxH] [--seed N] [--format ascii|ron|png] \
+[--tileset <sheet without extension>] [--out FILE] [--min-walkable RATIO] [--exit-distance N] [--vault FILE]... [--example FILE] [--pattern-size N] [--floor-pairs FILE] [--generator RON] [--quality RON] [--metrics] [--batch N] [--lint]";
+
+#[derive(Clone, Copy, PartialEq)]
+enum Format {
+    Ascii,
+    Ron,
+    Png,
+}
+
+struct Args {
+    pairs: PathBuf,
+    width: u32,
+    height: u32,
+    seed: u64,
+    format: Format,
+    tileset: Option<PathBuf>, // `<tileset>.png` and `<tileset>.ron`, needed for png output
+    out: Option<PathBuf>,
+    min_walkable_ratio: f32,
+    min_exit_distance: i32,
+    vaults: Vec<PathBuf>,
+    example: Option<PathBuf>, // `.ron` or a Tiled `.csv` export
+    pattern_size: Option<NonZeroU32>,
+    floor_pairs: Option<PathBuf>,
+    generator: Generator, // `Wfc`, e.g. `Rooms(min_size: 4, max_size: 10)` or `Surface(chunk_size: 16)`
+    quality: Thresholds,  // e.g. `(min_loops: 2, max_dead_ends: 10)`, like `quality` in levels.ron
+    metrics: bool,        // print the metrics of a single map to stderr
+    batch: Option<usize>,
+    lint: bool, // check the pairs instead of generating
+}
+
+impl Args {
+    fn parse() -> Result<Self, String> {
+        let mut args = std::env::args().skip(1);
+        let mut parsed = Args {
+            pairs: PathBuf::new(),
+            width: 24,
+            height: 24,
+            seed: 0,
+            format: Format::Ascii,
+            tileset: None,
+            out: None,
+            min_walkable_ratio: Budget::default().min_walkable_ratio,
+            min_exit_distance: 8,
+            vaults: vec![],
+            example: None,
+            pattern_size: None,
+            floor_pairs: None,
+            generator: Generator::default(),
+            quality: Thresholds::default(),
+            metrics: false,
+            batch: None,
+            lint: false,
+        };
+        let mut pairs = None;
+
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or(format!("{} needs a value", arg));
+            match arg.as_str() {
+                "--size" => {
+                    let size = value()?;
+                    let mut dims = size.split('x').map(|d| d.parse::<u32>());
+                    match (dims.next(), dims.next(), dims.next()) {
+                        (Some(Ok(w)), Some(Ok(h)), None) if w > 2 && h > 2 => {
+                            parsed.width = w;
+                            parsed.height = h;
+                        }
+                        _ => return Err(format!("bad size {}, expected e.g. 24x24", size)),
+                    }
+                }
+                "--seed" => {
+                    parsed.seed = value()?.parse().map_err(|e| format!("bad seed {}", e))?
+                }
+                "--format" => {
+                    parsed.format = match value()?.as_str() {
+                        "ascii" => Format::Ascii,
+                        "ron" => Format::Ron,
+                        "png" => Format::Png,
+                        other => return Err(format!("unknown format {}", other)),
+                    }
+                }
+                "--tileset" => parsed.tileset = Some(value()?.into()),
+                "--out" => parsed.out = Some(value()?.into()),
+                "--min-walkable" => {
+                    parsed.min_walkable_ratio =
+                        value()?.parse().map_err(|e| format!("bad ratio {}", e))?
+                }
+                "--exit-distance" => {
+                    parsed.min_exit_distance = value()?
+                        .parse()
+                        .map_err(|e| format!("bad distance {}", e))?
+                }
+                "--vault" => parsed.vaults.push(value()?.into()),
+                "--example" => parsed.example = Some(value()?.into()),
+                "--floor-pairs" => parsed.floor_pairs = Some(value()?.into()),
+                "--pattern-size" => {
+                    parsed.pattern_size = Some(
+                        value()?
+                            .parse()
+                            .map_err(|_| "pattern size has to be a number above 0".to_string())?,
+                    )
+                }
+                "--generator" => {
+                    parsed.generator =
+                        ron::de::from_str(&value()?).map_err(|e| format!("bad generator {}", e))?
+                }
+                "--quality" => {
+                    parsed.quality = ron::de::from_str(&value()?)
+                        .map_err(|e| format!("bad quality thresholds {}", e))?
+                }
+                "--metrics" => parsed.metrics = true,
+                "--batch" => {
+                    parsed.batch = Some(value()?.parse().map_err(|e| format!("bad count {}", e))?)
+                }
+                "--lint" => parsed.lint = true,
+                "--help" | "-h" => return Err(USAGE.to_string()),
+                _ if arg.starts_with("--") => return Err(format!("unknown option {}", arg)),
+                _ => pairs = Some(PathBuf::from(arg)),
+            }
+        }
+
+        parsed.pairs = pairs.ok_or_else(|| USAGE.to_string())?;
+        if parsed.format == Format::Png
+            && !parsed.lint
+            && (parsed.tileset.is_none() || parsed.out.is_none())
+        {
+            return Err("png output needs --tileset and --out".to_string());
+        }
+        if parsed.pattern_size.is_some() && parsed.example.is_none() {
+            return Err("--pattern-size needs --example".to_string());
+        }
+
+        Ok(parsed)
+    }
+}
+
+struct Level {
+    seed: u64,
+    start: Coord,
+    exit: Coord,
+    walls: TileMap<RoomTile>,
+    floor: TileMap<FloorTile>,
+    outcome: Result<GenStats, GenError>,
+}
+
+/// Everything read from files that a level is generated from.
+struct Sources {
+    pairs: Pairs,
+    model: Model,
+    vaults: Vec<Vault>,
+    floor_pairs: Option<Pairs>,
+}
+
+/// Generates a level the same way the game does, `seed` is the level seed the game prints.
+/// Surface levels only come out as the chunks around the start.
+fn generate(args: &Args, sources: &Sources, seed: u64) -> Level {
+    let (width, height) = args.generator.map_size((args.width, args.height));
+    let map_size = Vector3::new(width, height, 1);
+    let tile_size = Vector3::new(32, 32, 1);
+    let mut walls = TileMap::<RoomTile>::new(map_size, tile_size, None);
+    let mut floor = TileMap::<FloorTile>::new(map_size, tile_size, None);
+
+    let mut rng = StdRng::seed_from_u64(seed);
+    let start = Coord::new(width as i32 / 2, height as i32 / 2);
+    let budget = Budget {
+        min_walkable_ratio: args.min_walkable_ratio,
+        thresholds: args.quality,
+        ..Default::default()
+    };
+
+    if let Generator::Surface { chunk_size } = args.generator {
+        let mut chunks = Chunks::new(sources.pairs.clone(), chunk_size, seed);
+        let outcome = chunks::gen_surface(&mut walls, &mut floor, &mut chunks, start, budget);
+        let exit = chunks::pick_exit(chunk_size, args.min_exit_distance, (-1, -1), &mut rng);
+        return Level {
+            seed,
+            start,
+            exit,
+            walls,
+            floor,
+            outcome,
+        };
+    }
+
+    let exit = mapgen::pick_exit(width, height, start, args.min_exit_distance, &mut rng);
+    let vaults = match args.generator {
+        Generator::Wfc => {
+            mapgen::place_vaults(&sources.vaults, width, height, &[start, exit], &mut rng)
+        }
+        Generator::Rooms { .. } | Generator::Surface { .. } => vec![],
+    };
+    let vault_exit = mapgen::vault_exit(&vaults);
+    let exit = vault_exit.unwrap_or(exit);
+
+    let outcome = match args.generator {
+        Generator::Wfc => mapgen::gen_map(
+            &mut walls,
+            &mut floor,
+            &sources.pairs,
+            &sources.model,
+            start,
+            exit,
+            &vaults,
+            budget,
+            &Progress::default(),
+            &mut rng,
+        ),
+        Generator::Rooms { min_size, max_size } => mapgen::gen_rooms(
+            &mut walls,
+            &mut floor,
+            &sources.pairs,
+            &sources.model,
+            start,
+            exit,
+            (min_size, max_size),
+            budget,
+            &Progress::default(),
+            &mut rng,
+        ),
+        Generator::Surface { .. } => unreachable!("surface levels return early"),
+    };
+
+    if let (Ok(_), Some(floor_pairs)) = (&outcome, &sources.floor_pairs) {
+        if let Err(e) = mapgen::gen_floor(
+            &mut walls,
+            &mut floor,
+            &sources.pairs,
+            floor_pairs,
+            budget,
+            &mut rng,
+        ) {
+            eprintln!("seed {}: leaving the floor plain, {}", seed, e);
+        }
+    }
+
+    // the game moves an exit that wasn't put down by a vault in to the nearest room
+    let exit = match (&outcome, vault_exit) {
+        (_, Some(vault_exit)) => vault_exit,
+        (Err(_), None) => exit,
+        (Ok(_), None) => Regions::new(&walls)
+            .nearest_room(exit, start, args.min_exit_distance)
+            .unwrap_or(exit),
+    };
+
+    Level {
+        seed,
+        start,
+        exit,
+        walls,
+        floor,
+        outcome,
+    }
+}
+
+fn tile_at<T: Tile>(map: &TileMap<T>, x: u32, y: u32) -> T {
+    map.get(&Point3::new(x, y, 0)).cloned().unwrap_or_default()
+}
+
+fn to_ascii(level: &Level, pairs: &Pairs) -> String {
+    let (width, height) = (level.walls.dimensions().x, level.walls.dimensions().y);
+    let mut out = String::new();
+
+    for y in 0..height {
+        for x in 0..width {
+            let here = Coord::new(x as i32, y as i32);
+            let def = tile_at(&level.walls, x, y).sprite.map(|s| pairs.tile(s));
+
+            out.push(match def {
+                _ if here == level.start => '@',
+                _ if here == level.exit => '>',
+                None => ' ',
+                Some(def) if def.has_tag(TileTag::Void) => ' ',
+                Some(def) if def.has_tag(TileTag::Door) => '+',
+                Some(def) if def.has_tag(TileTag::Decoration) => '*',
+                Some(def) if def.walkable => '.',
+                Some(_) => '#',
+            });
+        }
+        out.push('\n');
+    }
+
+    out
+}
+
+/// Sprite indices of both layers, row by row.
+#[derive(Serialize)]
+struct LevelFile {
+    seed: u64,
+    width: u32,
+    height: u32,
+    start: (i32, i32),
+    exit: (i32, i32),
+    walls: Vec<Vec<Option<usize>>>,
+    floor: Vec<Vec<Option<usize>>>,
+}
+
+fn to_ron(level: &Level) -> Result<String, String> {
+    let (width, height) = (level.walls.dimensions().x, level.walls.dimensions().y);
+    let rows = |layer: &dyn Fn(u32, u32) -> Option<usize>| -> Vec<Vec<Option<usize>>> {
+        (0..height)
+            .map(|y| (0..width).map(|x| layer(x, y)).collect())
+            .collect()
+    };
+
+    let file = LevelFile {
+        seed: level.seed,
+        width,
+        height,
+        start: (level.start.x, level.start.y),
+        exit: (level.exit.x, level.exit.y),
+        walls: rows(&|x, y| tile_at(&level.walls, x, y).sprite),
+        floor: rows(&|x, y| tile_at(&level.floor, x, y).sprite),
+    };
+
+    ron::ser::to_string_pretty(&file, ron::ser::PrettyConfig::default()).map_err(|e| e.to_string())
+}
+
+/// The parts of an amethyst `Grid` sprite sheet needed to cut out sprites.
+#[derive(Deserialize)]
+enum SheetFile {
+    Grid(GridSheet),
+}
+
+#[derive(Deserialize)]
+struct GridSheet {
+    texture_width: u32,
+    texture_height: u32,
+    columns: u32,
+    #[serde(default)]
+    rows: Option<u32>,
+    #[serde(default)]
+    cell_size: Option<(u32, u32)>,
+}
+
+impl GridSheet {
+    fn rows(&self) -> u32 {
+        self.rows
+            .unwrap_or_else(|| self.texture_height / (self.texture_width / self.columns))
+    }
+
+    fn sprites(&self) -> usize {
+        (self.columns * self.rows()) as usize
+    }
+}
+
+fn read_sheet(tileset: &PathBuf) -> Result<GridSheet, String> {
+    let sheet_path = tileset.with_extension("ron");
+    let sheet_ron =
+        fs::read_to_string(&sheet_path).map_err(|e| format!("{}: {}", sheet_path.display(), e))?;
+    let SheetFile::Grid(grid) = ron::de::from_str(&sheet_ron).map_err(|e| {
+        format!(
+            "{}: only Grid sheets are supported, {}",
+            sheet_path.display(),
+            e
+        )
+    })?;
+    Ok(grid)
+}
+
+fn to_png(level: &Level, tileset: &PathBuf, out: &PathBuf) -> Result<(), String> {
+    let grid = read_sheet(tileset)?;
+
+    let image_path = tileset.with_extension("png");
+    let sheet = image::open(&image_path)
+        .map_err(|e| format!("{}: {}", image_path.display(), e))?
+        .to_rgba();
+
+    let rows = grid.rows();
+    let (cell_w, cell_h) = grid.cell_size.unwrap_or((
+        grid.texture_width / grid.columns,
+        grid.texture_height / rows,
+    ));
+    let cut = |idx: usize| {
+        let (col, row) = (idx as u32 % grid.columns, idx as u32 / grid.columns);
+        imageops::crop_imm(&sheet, col * cell_w, row * cell_h, cell_w, cell_h).to_image()
+    };
+
+    let (width, height) = (level.walls.dimensions().x, level.walls.dimensions().y);
+    let mut image = RgbaImage::new(width * cell_w, height * cell_h);
+
+    for y in 0..height {
+        for x in 0..width {
+            // the floor is drawn first and the walls on top, like the game
+            let layers = [
+                tile_at(&level.floor, x, y).sprite,
+                tile_at(&level.walls, x, y).sprite,
+            ];
+            for idx in layers.iter().flatten() {
+                imageops::overlay(&mut image, &cut(*idx), x * cell_w, y * cell_h);
+            }
+        }
+    }
+
+    image
+        .save(out)
+        .map_err(|e| format!("{}: {}", out.display(), e))
+}
+
+fn write(args: &Args, text: &str) -> Result<(), String> {
+    match &args.out {
+        Some(out) => fs::write(out, text).map_err(|e| format!("{}: {}", out.display(), e)),
+        None => {
+            print!("{}", text);
+            Ok(())
+        }
+    }
+}
+
+fn single(args: &Args, sources: &Sources) -> Result<(), String> {
+    let level = generate(args, sources, args.seed);
+    match &level.outcome {
+        Ok(stats) if args.metrics => eprintln!("{:#?}", stats.metrics),
+        Ok(_) => (),
+        Err(e) => return Err(e.to_string()),
+    }
+
+    match args.format {
+        Format::Ascii => write(args, &to_ascii(&level, &sources.pairs)),
+        Format::Ron => write(args, &to_ron(&level)?),
+        Format::Png => to_png(
+            &level,
+            args.tileset.as_ref().unwrap(),
+            args.out.as_ref().unwrap(),
+        ),
+    }
+}
+
+/// Generates `count` maps from consecutive seeds and reports how generation went.
+fn batch(args: &Args, sources: &Sources, count: usize) {
+    let mut failed = 0;
+    let (mut attempts, mut contradictions) = (0, 0);
+    let mut ratios = vec![];
+    let mut times = vec![];
+    let mut measured: Vec<Metrics> = vec![];
+
+    for seed in args.seed..args.seed + count as u64 {
+        let level = generate(args, sources, seed);
+        let (tries, fails, ratio, elapsed, shape) = match &level.outcome {
+            Ok(stats) => {
+                measured.push(stats.metrics.clone());
+                (
+                    stats.attempts,
+                    stats.contradictions,
+                    stats.walkable_ratio,
+                    stats.elapsed,
+                    stats.metrics.to_string(),
+                )
+            }
+            Err(e) => {
+                failed += 1;
+                (
+                    e.attempts,
+                    e.contradictions,
+                    e.best_walkable_ratio,
+                    e.elapsed,
+                    format!("{} below quality", e.rejected),
+                )
+            }
+        };
+
+        println!(
+            "seed {} {} attempts {} contradictions {} walkable {:.0}% time {:.2}s {}",
+            seed,
+            if level.outcome.is_ok() {
+                "ok"
+            } else {
+                "failed"
+            },
+            tries,
+            fails,
+            ratio * 100.,
+            elapsed.as_secs_f32(),
+            shape
+        );
+
+        attempts += tries;
+        contradictions += fails;
+        ratios.push(ratio);
+        times.push(elapsed);
+    }
+
+    let mean = |total: f32| total / count.max(1) as f32;
+    println!("maps {}, failed {}", count, failed);
+    println!(
+        "walkable ratio mean {:.0}%",
+        mean(ratios.iter().sum::<f32>()) * 100.
+    );
+    println!(
+        "attempts mean {:.1}, retries {}",
+        mean(attempts as f32),
+        attempts.saturating_sub(count)
+    );
+    println!(
+        "contradiction rate {:.0}% of attempts",
+        contradictions as f32 / attempts.max(1) as f32 * 100.
+    );
+    println!(
+        "time mean {:.2}s, max {:.2}s",
+        mean(times.iter().sum::<Duration>().as_secs_f32()),
+        times
+            .iter()
+            .max()
+            .copied()
+            .unwrap_or_default()
+            .as_secs_f32()
+    );
+
+    // only the maps that were kept are measured
+    let average = |metric: &dyn Fn(&Metrics) -> f32| {
+        measured.iter().map(metric).sum::<f32>() / measured.len().max(1) as f32
+    };
+    println!(
+        "dead ends mean {:.1}, loops {:.1}, chokepoints {:.1}",
+        average(&|m| m.dead_ends as f32),
+        average(&|m| m.loops as f32),
+        average(&|m| m.chokepoints as f32)
+    );
+    println!(
+        "room ratio mean {:.0}%, longest path {:.1}, largest open area {:.1}",
+        average(&|m| m.room_ratio()) * 100.,
+        average(&|m| m.longest_path as f32),
+        average(&|m| m.largest_open_area as f32)
+    );
+}
+
+fn read_ron<T: serde::de::DeserializeOwned>(path: &PathBuf) -> Result<T, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    ron::de::from_str(&text).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+fn read_example(path: &PathBuf) -> Result<Example, String> {
+    if path.extension().map_or(false, |ext| ext == "csv") {
+        let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        Example::from_tiled_csv(&text).map_err(|e| format!("{}: {}", path.display(), e))
+    } else {
+        read_ron(path)
+    }
+}
+
+/// Prints every problem in the pairs, fails when one of them stops generation.
+fn lint(args: &Args, pairs: &Pairs) -> Result<(), String> {
+    let sprites = match &args.tileset {
+        Some(tileset) => Some(read_sheet(tileset)?.sprites()),
+        None => None,
+    };
+    let problems = sanity_lib::lint::lint(pairs, sprites);
+
+    for problem in &problems {
+        let severity = if problem.is_error() {
+            "error"
+        } else {
+            "warning"
+        };
+        println!("{}: {}", severity, problem);
+    }
+
+    match problems.iter().filter(|p| p.is_error()).count() {
+        0 => {
+            println!("{}: {} warnings", args.pairs.display(), problems.len());
+            Ok(())
+        }
+        errors => Err(format!("{}: {} errors", args.pairs.display(), errors)),
+    }
+}
+
+fn run() -> Result<(), String> {
+    let args = Args::parse()?;
+    let pairs: Pairs = read_ron(&args.pairs)?;
+    if args.lint {
+        return lint(&args, &pairs);
+    }
+    let vaults = args
+        .vaults
+        .iter()
+        .map(|path| read_ron(path))
+        .collect::<Result<Vec<Vault>, String>>()?;
+
+    // an example replaces the pairs in the file with the ones it shows
+    let (pairs, model) = match (args.example.as_ref().map(read_example), args.pattern_size) {
+        (Some(example), Some(size)) => {
+            let example = example?;
+            (example.learn(&pairs), Model::Overlapping { example, size })
+        }
+        (Some(example), None) => (example?.learn(&pairs), Model::Pairs),
+        (None, _) => (pairs, Model::Pairs),
+    };
+    let floor_pairs = match &args.floor_pairs {
+        Some(path) => Some(read_ron(path)?),
+        None => None,
+    };
+    let sources = Sources {
+        pairs,
+        model,
+        vaults,
+        floor_pairs,
+    };
+
+    match args.batch {
+        Some(count) => {
+            batch(&args, &sources, count);
+            Ok(())
+        }
+        None => single(&args, &sources),
+    }
+}
+
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}